@@ -0,0 +1,259 @@
+// A frontend for plain terminals (over SSH, or wherever there is no SDL):
+// raw mode, the alternate screen and 24-bit ANSI colours. Only the cells that
+// changed since the last frame are sent.
+
+use std::io::{self, BufWriter, Stdout, Write};
+use std::time::Duration;
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{
+    self, DisableMouseCapture, EnableMouseCapture, KeyCode as TermKeyCode, KeyEventKind,
+    KeyEventState, KeyModifiers, KeyboardEnhancementFlags, MouseButton, MouseEventKind,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::style::{Color as TermColor, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+use crate::colors::Color;
+use crate::grid::Grid;
+use crate::terminal::{Canvas, Event, Key, KeyCode, Mouse, Terminal};
+
+
+pub struct Ansi {
+    // What the game has drawn, and what the terminal is currently showing
+    // (None when it has to be redrawn from scratch)
+    screen: Grid,
+    shown: Option<Grid>,
+    out: BufWriter<Stdout>,
+    frame_time: Duration,
+    // Whether the terminal tells numpad keys apart (the kitty keyboard
+    // protocol); without it a numpad digit is just a digit
+    keypad: bool,
+    closed: bool,
+}
+
+
+impl Ansi {
+    // Takes over the terminal until dropped. Fails if the terminal is too
+    // small to fit the whole screen.
+    pub fn open(width: i32, height: i32, limit_fps: i32) -> io::Result<Self> {
+        let (columns, rows) = terminal::size()?;
+        if (columns as i32) < width || (rows as i32) < height {
+            return Err(io::Error::other(format!(
+                "the terminal is {}x{}, but the game needs at least {}x{}",
+                columns, rows, width, height
+            )));
+        }
+
+        terminal::enable_raw_mode()?;
+        let mut out = BufWriter::new(io::stdout());
+        execute!(out, EnterAlternateScreen, Hide, EnableMouseCapture, Clear(ClearType::All))?;
+        let keypad = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if keypad {
+            execute!(
+                out,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES)
+            )?;
+        }
+        Ok(Ansi {
+            screen: Grid::new(width, height),
+            shown: None,
+            out,
+            frame_time: Duration::from_millis(1000 / limit_fps.max(1) as u64),
+            keypad,
+            closed: false,
+        })
+    }
+
+    fn draw_changes(&mut self) -> io::Result<()> {
+        let (width, height) = self.screen.size();
+        let mut colors: Option<(Color, Color)> = None;
+        let mut cursor: Option<(i32, i32)> = None;
+        for y in 0..height {
+            for x in 0..width {
+                let cell = match self.screen.get(x, y) {
+                    Some(cell) => *cell,
+                    None => continue,
+                };
+                if self.shown.as_ref().and_then(|shown| shown.get(x, y)) == Some(&cell) {
+                    continue;
+                }
+
+                if cursor != Some((x, y)) {
+                    queue!(self.out, MoveTo(x as u16, y as u16))?;
+                }
+                if colors != Some((cell.fg, cell.bg)) {
+                    queue!(
+                        self.out,
+                        SetForegroundColor(convert_color(cell.fg)),
+                        SetBackgroundColor(convert_color(cell.bg))
+                    )?;
+                    colors = Some((cell.fg, cell.bg));
+                }
+                queue!(self.out, Print(cell.glyph))?;
+                cursor = Some((x + 1, y));
+            }
+        }
+        self.out.flush()
+    }
+
+    fn convert_event(&mut self, event: event::Event) -> Option<Event> {
+        match event {
+            event::Event::Key(key) if key.kind != KeyEventKind::Release => {
+                // Raw mode swallows Ctrl+C, so treat it like closing the window
+                if key.code == TermKeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+                    self.closed = true;
+                    return None;
+                }
+                let on_keypad = key.state.contains(KeyEventState::KEYPAD);
+                let code = match key.code {
+                    TermKeyCode::Char(c @ '0'..='9') if on_keypad => {
+                        KeyCode::NumPad(c as u8 - b'0')
+                    },
+                    TermKeyCode::Char(c) => KeyCode::Char(c),
+                    // Numpad 5 with Num Lock off
+                    TermKeyCode::KeypadBegin => KeyCode::NumPad(5),
+                    TermKeyCode::Up => KeyCode::Up,
+                    TermKeyCode::Down => KeyCode::Down,
+                    TermKeyCode::Left => KeyCode::Left,
+                    TermKeyCode::Right => KeyCode::Right,
+                    TermKeyCode::Home => KeyCode::Home,
+                    TermKeyCode::End => KeyCode::End,
+                    TermKeyCode::PageUp => KeyCode::PageUp,
+                    TermKeyCode::PageDown => KeyCode::PageDown,
+                    TermKeyCode::Enter => KeyCode::Enter,
+                    TermKeyCode::Esc => KeyCode::Escape,
+                    TermKeyCode::Backspace => KeyCode::Backspace,
+                    TermKeyCode::Tab => KeyCode::Tab,
+                    _ => return None,
+                };
+                Some(Event::Key(Key {
+                    code,
+                    alt: key.modifiers.contains(KeyModifiers::ALT),
+                    ctrl: key.modifiers.contains(KeyModifiers::CONTROL),
+                }))
+            },
+            event::Event::Mouse(mouse) => {
+                let mut converted = Mouse {
+                    cx: mouse.column as i32,
+                    cy: mouse.row as i32,
+                    ..Default::default()
+                };
+                match mouse.kind {
+                    MouseEventKind::Down(MouseButton::Left) => converted.lbutton_pressed = true,
+                    MouseEventKind::Down(MouseButton::Right) => converted.rbutton_pressed = true,
+                    MouseEventKind::Moved | MouseEventKind::Drag(_) => {},
+                    _ => return None,
+                }
+                Some(Event::Mouse(converted))
+            },
+            event::Event::Resize(..) => {
+                // Whatever was on screen may be gone, so start over
+                self.shown = None;
+                None
+            },
+            _ => None,
+        }
+    }
+}
+
+
+impl Drop for Ansi {
+    fn drop(&mut self) {
+        // Nothing sensible to do if the terminal can't be restored
+        if self.keypad {
+            let _ = execute!(self.out, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(self.out, DisableMouseCapture, ResetColor, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+
+fn convert_color(color: Color) -> TermColor {
+    TermColor::Rgb { r: color.r, g: color.g, b: color.b }
+}
+
+
+impl Canvas for Ansi {
+    fn size(&self) -> (i32, i32) {
+        self.screen.size()
+    }
+
+    fn clear(&mut self) {
+        self.screen.clear();
+    }
+
+    fn put_glyph(&mut self, x: i32, y: i32, glyph: char, fg: Color) {
+        self.screen.put_glyph(x, y, glyph, fg);
+    }
+
+    fn set_bg(&mut self, x: i32, y: i32, bg: Color) {
+        self.screen.set_bg(x, y, bg);
+    }
+}
+
+
+impl Terminal for Ansi {
+    type Layer = Grid;
+
+    fn new_layer(&mut self, width: i32, height: i32) -> Grid {
+        Grid::new(width, height)
+    }
+
+    fn blit(&mut self, layer: &Grid, to: (i32, i32), fg_alpha: f32, bg_alpha: f32) {
+        self.screen.blit(layer, to, fg_alpha, bg_alpha);
+    }
+
+    fn flush(&mut self) {
+        match self.draw_changes() {
+            Ok(()) => self.shown = Some(self.screen.clone()),
+            // A terminal that can't be written to is as good as closed
+            Err(_) => self.closed = true,
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    // The terminal's own window decides its size
+    fn toggle_fullscreen(&mut self) {}
+
+    fn wait_for_keypress(&mut self) -> Option<Key> {
+        while !self.closed {
+            match event::read() {
+                Ok(event) => {
+                    if let Some(Event::Key(key)) = self.convert_event(event) {
+                        return Some(key);
+                    }
+                    // Put back whatever a resize wiped while waiting
+                    if self.shown.is_none() {
+                        self.flush();
+                    }
+                },
+                Err(_) => self.closed = true,
+            }
+        }
+        None
+    }
+
+    // Waits up to a frame for input, which also paces the game loop
+    fn check_for_event(&mut self) -> Option<Event> {
+        match event::poll(self.frame_time) {
+            Ok(true) => match event::read() {
+                Ok(event) => self.convert_event(event),
+                Err(_) => {
+                    self.closed = true;
+                    None
+                },
+            },
+            Ok(false) => None,
+            Err(_) => {
+                self.closed = true;
+                None
+            },
+        }
+    }
+}
@@ -0,0 +1,55 @@
+// 24-bit colours and the named ones the game uses, with the same values as
+// libtcod's so the game looks the same whichever frontend draws it
+
+use serde::{Deserialize, Serialize};
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+
+impl Color {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Color { r, g, b }
+    }
+}
+
+
+// Mixes two colours: coefficient 0 is all `from`, 1 is all `to`
+pub fn lerp(from: Color, to: Color, coefficient: f32) -> Color {
+    let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * coefficient).round() as u8;
+    Color::new(mix(from.r, to.r), mix(from.g, to.g), mix(from.b, to.b))
+}
+
+
+pub const BLACK: Color = Color::new(0, 0, 0);
+pub const LIGHT_GREY: Color = Color::new(159, 159, 159);
+pub const WHITE: Color = Color::new(255, 255, 255);
+
+pub const RED: Color = Color::new(255, 0, 0);
+pub const LIGHT_RED: Color = Color::new(255, 63, 63);
+pub const DARK_RED: Color = Color::new(191, 0, 0);
+pub const DARKER_RED: Color = Color::new(127, 0, 0);
+
+pub const ORANGE: Color = Color::new(255, 127, 0);
+pub const DARKER_ORANGE: Color = Color::new(127, 63, 0);
+
+pub const YELLOW: Color = Color::new(255, 255, 0);
+pub const LIGHT_YELLOW: Color = Color::new(255, 255, 63);
+pub const GOLD: Color = Color::new(229, 191, 0);
+
+pub const GREEN: Color = Color::new(0, 255, 0);
+pub const LIGHT_GREEN: Color = Color::new(63, 255, 63);
+pub const DARKER_GREEN: Color = Color::new(0, 127, 0);
+pub const DESATURATED_GREEN: Color = Color::new(63, 127, 63);
+
+pub const LIGHT_CYAN: Color = Color::new(63, 255, 255);
+pub const SKY: Color = Color::new(0, 191, 255);
+pub const LIGHT_BLUE: Color = Color::new(63, 63, 255);
+
+pub const VIOLET: Color = Color::new(127, 0, 255);
+pub const LIGHT_VIOLET: Color = Color::new(159, 63, 255);
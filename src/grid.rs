@@ -0,0 +1,141 @@
+// An in-memory grid of cells, for frontends that keep their own copy of the
+// screen rather than drawing into a library's consoles
+
+use std::fmt::Write;
+
+use crate::colors::{self, Color};
+use crate::terminal::Canvas;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cell {
+    pub glyph: char,
+    pub fg: Color,
+    pub bg: Color,
+}
+
+
+const BLANK: Cell = Cell { glyph: ' ', fg: colors::WHITE, bg: colors::BLACK };
+
+// Symbols standing in for colours in a dump, in order of first use
+const PALETTE_SYMBOLS: &str = "0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+
+// A grid of cells in memory, row-major like the map
+#[derive(Clone)]
+pub struct Grid {
+    width: i32,
+    height: i32,
+    cells: Vec<Cell>,
+}
+
+
+impl Grid {
+    pub fn new(width: i32, height: i32) -> Self {
+        Grid {
+            width,
+            height,
+            cells: vec![BLANK; (width * height) as usize],
+        }
+    }
+
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        if x >= 0 && y >= 0 && x < self.width && y < self.height {
+            Some((y * self.width + x) as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn get(&self, x: i32, y: i32) -> Option<&Cell> {
+        self.index(x, y).map(|i| &self.cells[i])
+    }
+
+    fn get_mut(&mut self, x: i32, y: i32) -> Option<&mut Cell> {
+        self.index(x, y).map(move |i| &mut self.cells[i])
+    }
+
+    // Draws another grid over this one, blending its colours in by the
+    // given alphas. Blank cells of a see-through layer leave the glyph
+    // underneath showing.
+    pub fn blit(&mut self, layer: &Grid, to: (i32, i32), fg_alpha: f32, bg_alpha: f32) {
+        for y in 0..layer.height {
+            for x in 0..layer.width {
+                let over = layer.cells[(y * layer.width + x) as usize];
+                let under = match self.get_mut(to.0 + x, to.1 + y) {
+                    Some(cell) => cell,
+                    None => continue,
+                };
+                under.bg = colors::lerp(under.bg, over.bg, bg_alpha);
+                if over.glyph != ' ' || fg_alpha >= 1.0 {
+                    under.glyph = over.glyph;
+                    under.fg = colors::lerp(under.fg, over.fg, fg_alpha);
+                } else {
+                    under.fg = colors::lerp(under.fg, over.bg, bg_alpha);
+                }
+            }
+        }
+    }
+
+    // The glyphs as lines of text, followed by the foreground and background
+    // colours of every cell as a symbol each, with a legend mapping the
+    // symbols back to colours
+    pub fn dump(&self) -> String {
+        let mut palette: Vec<Color> = vec![];
+        let mut symbol_for = |color: Color| {
+            let index = match palette.iter().position(|&c| c == color) {
+                Some(index) => index,
+                None => {
+                    palette.push(color);
+                    palette.len() - 1
+                },
+            };
+            PALETTE_SYMBOLS.chars().nth(index).unwrap_or('?')
+        };
+
+        let mut glyphs = String::new();
+        let mut foreground = String::new();
+        let mut background = String::new();
+        for row in self.cells.chunks(self.width as usize) {
+            glyphs.extend(row.iter().map(|cell| cell.glyph));
+            foreground.extend(row.iter().map(|cell| symbol_for(cell.fg)));
+            background.extend(row.iter().map(|cell| symbol_for(cell.bg)));
+            glyphs.push('\n');
+            foreground.push('\n');
+            background.push('\n');
+        }
+
+        let mut dump = format!(
+            "glyphs:\n{}\nforeground:\n{}\nbackground:\n{}\npalette:\n",
+            glyphs, foreground, background
+        );
+        for (symbol, color) in PALETTE_SYMBOLS.chars().zip(palette) {
+            writeln!(dump, "{} #{:02x}{:02x}{:02x}", symbol, color.r, color.g, color.b).unwrap();
+        }
+        dump
+    }
+}
+
+
+impl Canvas for Grid {
+    fn size(&self) -> (i32, i32) {
+        (self.width, self.height)
+    }
+
+    fn clear(&mut self) {
+        self.cells.fill(BLANK);
+    }
+
+    fn put_glyph(&mut self, x: i32, y: i32, glyph: char, fg: Color) {
+        if let Some(cell) = self.get_mut(x, y) {
+            cell.glyph = glyph;
+            cell.fg = fg;
+        }
+    }
+
+    fn set_bg(&mut self, x: i32, y: i32, bg: Color) {
+        if let Some(cell) = self.get_mut(x, y) {
+            cell.bg = bg;
+        }
+    }
+}
@@ -0,0 +1,100 @@
+// A terminal with no display: the screen only exists in memory, key presses
+// come from a script, and what ends up on screen can be dumped as text. Lets
+// the game run where there is no window to open, such as on CI.
+
+use std::collections::VecDeque;
+
+use crate::colors::Color;
+use crate::grid::Grid;
+use crate::terminal::{Canvas, Event, Key, Terminal};
+
+
+// Runs until the script runs out, at which point it counts as closed
+pub struct Headless {
+    screen: Grid,
+    script: VecDeque<Key>,
+    closed: bool,
+}
+
+
+impl Headless {
+    pub fn new(width: i32, height: i32, script: Vec<Key>) -> Self {
+        Headless {
+            screen: Grid::new(width, height),
+            script: script.into(),
+            closed: false,
+        }
+    }
+
+    pub fn dump(&self) -> String {
+        self.screen.dump()
+    }
+
+    fn next_key(&mut self) -> Option<Key> {
+        let key = self.script.pop_front();
+        if key.is_none() {
+            self.closed = true;
+        }
+        key
+    }
+}
+
+
+impl Canvas for Headless {
+    fn size(&self) -> (i32, i32) {
+        self.screen.size()
+    }
+
+    fn clear(&mut self) {
+        self.screen.clear();
+    }
+
+    fn put_glyph(&mut self, x: i32, y: i32, glyph: char, fg: Color) {
+        self.screen.put_glyph(x, y, glyph, fg);
+    }
+
+    fn set_bg(&mut self, x: i32, y: i32, bg: Color) {
+        self.screen.set_bg(x, y, bg);
+    }
+}
+
+
+impl Terminal for Headless {
+    type Layer = Grid;
+
+    fn new_layer(&mut self, width: i32, height: i32) -> Grid {
+        Grid::new(width, height)
+    }
+
+    fn blit(&mut self, layer: &Grid, to: (i32, i32), fg_alpha: f32, bg_alpha: f32) {
+        self.screen.blit(layer, to, fg_alpha, bg_alpha);
+    }
+
+    // Nothing to show: the screen is only looked at through dump()
+    fn flush(&mut self) {}
+
+    fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    fn toggle_fullscreen(&mut self) {}
+
+    fn wait_for_keypress(&mut self) -> Option<Key> {
+        self.next_key()
+    }
+
+    fn check_for_event(&mut self) -> Option<Event> {
+        self.next_key().map(Event::Key)
+    }
+}
+
+
+// A script is a whitespace-separated list of key names (see Key::from_str).
+// Lines starting with # are comments.
+pub fn parse_script(text: &str) -> Result<Vec<Key>, String> {
+    text.lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .flat_map(str::split_whitespace)
+        .map(str::parse)
+        .collect()
+}
@@ -1,60 +1,937 @@
 use std::cmp;
-use tcod::colors::*;
-use tcod::console::*;
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::Path;
+use std::process;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use ansi::Ansi;
+use colors::*;
+use headless::Headless;
+use terminal::{Align, Canvas, Event, Key, KeyCode, Mouse, Terminal};
+
+mod ansi;
+mod colors;
+mod grid;
+mod headless;
+#[cfg(feature = "tcod")]
+mod tcod_terminal;
+mod terminal;
 
 const SCREEN_WIDTH: i32 = 80;
 const SCREEN_HEIGHT: i32 = 50;
 const SCREEN_ORIGIN: (i32, i32) = (0, 0);
 
+// Part of the screen the map is drawn into
+const VIEW_WIDTH: i32 = 80;
+const VIEW_HEIGHT: i32 = 45;
+
+// Status panel in the rows below the map
+const PANEL_HEIGHT: i32 = SCREEN_HEIGHT - VIEW_HEIGHT;
+const PANEL_Y: i32 = VIEW_HEIGHT;
+const BAR_WIDTH: i32 = 20;
+const MSG_X: i32 = BAR_WIDTH + 2;
+const MSG_WIDTH: i32 = SCREEN_WIDTH - BAR_WIDTH - 2;
+const MSG_HEIGHT: i32 = PANEL_HEIGHT;
+// Older messages are dropped from the log (and the save file) past this
+const MAX_MESSAGES: usize = 100;
+
+// Default level size; room and monster counts are tuned for this area and
+// scaled for bigger or smaller maps
 const MAP_WIDTH: i32 = 80;
 const MAP_HEIGHT: i32 = 45;
+const MIN_MAP_SIZE: i32 = 20;
+// Keeps tile counts (and the sums scaled by them) well within i32
+const MAX_MAP_SIZE: i32 = 500;
+
+const ROOM_MAX_SIZE: i32 = 10;
+const ROOM_MIN_SIZE: i32 = 6;
+const MAX_ROOMS: i32 = 30;
+
+// BSP generation: how many times the map is halved, and the smallest leaf
+// that still fits a minimum-size room
+const BSP_DEPTH: i32 = 4;
+const BSP_MIN_LEAF_SIZE: i32 = ROOM_MIN_SIZE + 2;
+
+// Cave generation: initial wall density, then smoothing passes where a floor
+// tile turns to wall with at least CAVE_BIRTH_LIMIT wall neighbours and a
+// wall stays wall with at least CAVE_SURVIVAL_LIMIT
+const CAVE_WALL_CHANCE: f64 = 0.45;
+const CAVE_SMOOTH_PASSES: i32 = 5;
+const CAVE_BIRTH_LIMIT: usize = 5;
+const CAVE_SURVIVAL_LIMIT: usize = 4;
+// Disconnected pockets smaller than this are filled in, bigger ones tunnelled
+const CAVE_MIN_REGION_SIZE: usize = 20;
 
 const COLOUR_DARK_WALL: Color = Color { r: 0, g: 0, b: 100, };
+const COLOUR_LIGHT_WALL: Color = Color { r: 130, g: 110, b: 50, };
 const COLOUR_DARK_GROUND: Color = Color { r: 50, g: 50, b: 150,};
+const COLOUR_LIGHT_GROUND: Color = Color { r: 200, g: 180, b: 50, };
+
+const TORCH_RADIUS: i32 = 10;
+
+// Octant transforms for shadowcasting: (xx, xy, yx, yy)
+const FOV_OCTANTS: [(i32, i32, i32, i32); 8] = [
+    (1, 0, 0, -1),
+    (0, 1, -1, 0),
+    (0, 1, 1, 0),
+    (1, 0, 0, 1),
+    (-1, 0, 0, 1),
+    (0, -1, 1, 0),
+    (0, -1, -1, 0),
+    (-1, 0, 0, -1),
+];
 
 const OPAQUE: f32 = 1.0;
 // const TRANSPARENT: f32 = 0.0;
+const MENU_BACKGROUND_ALPHA: f32 = 0.7;
+
+const MAIN_MENU_WIDTH: i32 = 24;
 
 const LIMIT_FPS: i32 = 20;
 
-struct Tcod {
-    root: Root,
-    con: Offscreen,
+// The player is always the first object
+const PLAYER: usize = 0;
+
+const MAX_MONSTERS: i32 = 15;
+const MAX_ITEMS: i32 = 10;
+
+// One inventory slot per menu letter
+const INVENTORY_SIZE: usize = 26;
+const INVENTORY_WIDTH: i32 = 50;
+
+// Experience needed to leave a level: LEVEL_UP_BASE + level * LEVEL_UP_FACTOR
+const LEVEL_UP_BASE: i32 = 200;
+const LEVEL_UP_FACTOR: i32 = 150;
+const LEVEL_SCREEN_WIDTH: i32 = 40;
+const CHARACTER_SCREEN_WIDTH: i32 = 30;
+
+// Waiting in place (on its own or while resting) heals 1 HP every this many
+// turns
+const REGEN_INTERVAL: u32 = 10;
+
+const HEAL_AMOUNT: i32 = 10;
+const LIGHTNING_DAMAGE: i32 = 20;
+const LIGHTNING_RANGE: f32 = 5.0;
+const CONFUSE_RANGE: f32 = 8.0;
+const CONFUSE_NUM_TURNS: i32 = 10;
+const FIREBALL_RADIUS: f32 = 3.0;
+const FIREBALL_DAMAGE: i32 = 12;
+
+const SAVE_FILE: &str = "savegame";
+const KEYBINDINGS_FILE: &str = "keybindings.cfg";
+// Bump whenever a change to the saved structs breaks older save files
+const SAVE_VERSION: u32 = 9;
+
+// Everything to do with showing the game to the player, on whichever
+// terminal it is running in
+struct Ui<T: Terminal> {
+    root: T,
+    con: T::Layer,
+    panel: T::Layer,
+    camera: Camera,
+    bindings: KeyBindings,
+    key: Option<Key>,
+    mouse: Mouse,
 }
 
 
-#[derive(Debug)]
+// The window onto the map: which map position is drawn at the top-left of
+// the view
+#[derive(Clone, Copy, Debug)]
+struct Camera {
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+}
+
+
+impl Camera {
+    pub fn new(width: i32, height: i32) -> Self {
+        Camera { x: 0, y: 0, width, height }
+    }
+
+    // Centres on the target, without scrolling past the map edges. Maps
+    // smaller than the view are centred instead.
+    pub fn follow(&mut self, target_x: i32, target_y: i32, map: &Map) {
+        self.x = if map.width <= self.width {
+            (map.width - self.width) / 2
+        } else {
+            (target_x - self.width / 2).clamp(0, map.width - self.width)
+        };
+        self.y = if map.height <= self.height {
+            (map.height - self.height) / 2
+        } else {
+            (target_y - self.height / 2).clamp(0, map.height - self.height)
+        };
+    }
+
+    pub fn screen_to_map(&self, screen_x: i32, screen_y: i32) -> (i32, i32) {
+        (screen_x + self.x, screen_y + self.y)
+    }
+
+    // None when the map position is outside the view
+    pub fn map_to_screen(&self, map_x: i32, map_y: i32) -> Option<(i32, i32)> {
+        let (x, y) = (map_x - self.x, map_y - self.y);
+        if x >= 0 && y >= 0 && x < self.width && y < self.height {
+            Some((x, y))
+        } else {
+            None
+        }
+    }
+}
+
+
+#[derive(Debug, Serialize, Deserialize)]
 struct Object {
     x: i32,
     y: i32,
     glyph: char,
+    name: String,
     color: Color,
+    blocks: bool,
+    always_visible: bool,
+    alive: bool,
+    fighter: Option<Fighter>,
+    ai: Option<Ai>,
+    stairs: Option<Stairs>,
+    item: Option<Item>,
+    level: i32,
+    equipment: Option<Equipment>,
+    // Gear carried outside the inventory, as monsters do; only what is
+    // equipped counts towards stats
+    worn: Vec<Equipment>,
+    // Whether gear comes from game.inventory instead of `worn`
+    uses_inventory: bool,
 }
 
 
 impl Object {
-    pub fn new(x: i32, y: i32, glyph: char, color: Color) -> Self {
-        Object { x: x, y: y, glyph: glyph, color: color }
+    pub fn new(x: i32, y: i32, glyph: char, name: &str, color: Color, blocks: bool) -> Self {
+        Object {
+            x,
+            y,
+            glyph,
+            name: name.into(),
+            color,
+            blocks,
+            always_visible: false,
+            alive: false,
+            fighter: None,
+            ai: None,
+            stairs: None,
+            item: None,
+            level: 1,
+            equipment: None,
+            worn: vec![],
+            uses_inventory: false,
+        }
     }
     
-    pub fn move_by(&mut self, dx: i32, dy: i32, game: &Game) {
-        if !game.map[(self.x + dx) as usize][(self.y + dy) as usize].blocked {
-            self.x += dx;
-            self.y += dy;
+    pub fn pos(&self) -> (i32, i32) {
+        (self.x, self.y)
+    }
+
+    pub fn distance_to(&self, other: &Object) -> f32 {
+        let dx = other.x - self.x;
+        let dy = other.y - self.y;
+        ((dx.pow(2) + dy.pow(2)) as f32).sqrt()
+    }
+
+    // Returns the experience the killing blow earns, if this was it
+    pub fn take_damage(&mut self, damage: i32, game: &mut Game) -> Option<i32> {
+        if let Some(fighter) = self.fighter.as_mut() {
+            if damage > 0 {
+                fighter.hp -= damage;
+            }
+        }
+
+        if let Some(fighter) = self.fighter {
+            if fighter.hp <= 0 {
+                self.alive = false;
+                fighter.on_death.callback(self, game);
+                return Some(fighter.xp);
+            }
+        }
+        None
+    }
+
+    pub fn gain_xp(&mut self, xp: i32) {
+        if let Some(ref mut fighter) = self.fighter {
+            fighter.xp += xp;
+        }
+    }
+
+    pub fn distance(&self, x: i32, y: i32) -> f32 {
+        (((x - self.x).pow(2) + (y - self.y).pow(2)) as f32).sqrt()
+    }
+
+    // Heal by the given amount, without going over the maximum
+    pub fn heal(&mut self, amount: i32, game: &Game) {
+        let max_hp = self.max_hp(game);
+        if let Some(ref mut fighter) = self.fighter {
+            fighter.hp = cmp::min(fighter.hp + amount, max_hp);
+        }
+    }
+
+    // Taking off a max HP bonus can leave hp above the new maximum, so
+    // call this after anything is taken off
+    pub fn clamp_hp(&mut self, game: &Game) {
+        let max_hp = self.max_hp(game);
+        if let Some(ref mut fighter) = self.fighter {
+            fighter.hp = cmp::min(fighter.hp, max_hp);
+        }
+    }
+
+    // Effective stats: the fighter's base values plus whatever is equipped
+    pub fn power(&self, game: &Game) -> i32 {
+        let base_power = self.fighter.map_or(0, |f| f.base_power);
+        let bonus: i32 = self.get_all_equipped(game).iter().map(|e| e.power_bonus).sum();
+        base_power + bonus
+    }
+
+    pub fn defense(&self, game: &Game) -> i32 {
+        let base_defense = self.fighter.map_or(0, |f| f.base_defense);
+        let bonus: i32 = self.get_all_equipped(game).iter().map(|e| e.defense_bonus).sum();
+        base_defense + bonus
+    }
+
+    pub fn max_hp(&self, game: &Game) -> i32 {
+        let base_max_hp = self.fighter.map_or(0, |f| f.base_max_hp);
+        let bonus: i32 = self.get_all_equipped(game).iter().map(|e| e.max_hp_bonus).sum();
+        base_max_hp + bonus
+    }
+
+    pub fn get_all_equipped(&self, game: &Game) -> Vec<Equipment> {
+        let gear: Vec<Equipment> = if self.uses_inventory {
+            game.inventory.iter().filter_map(|item| item.equipment).collect()
+        } else {
+            self.worn.clone()
+        };
+        gear.into_iter().filter(|equipment| equipment.equipped).collect()
+    }
+
+    pub fn equip(&mut self, messages: &mut Messages) {
+        if let Some(ref mut equipment) = self.equipment {
+            if !equipment.equipped {
+                equipment.equipped = true;
+                messages.add(
+                    format!("Equipped {} on {}.", self.name, equipment.slot),
+                    LIGHT_GREEN,
+                );
+            }
+        }
+    }
+
+    pub fn dequip(&mut self, messages: &mut Messages) {
+        if let Some(ref mut equipment) = self.equipment {
+            if equipment.equipped {
+                equipment.equipped = false;
+                messages.add(
+                    format!("Dequipped {} from {}.", self.name, equipment.slot),
+                    LIGHT_YELLOW,
+                );
+            }
+        }
+    }
+
+    pub fn attack(&mut self, target: &mut Object, game: &mut Game) {
+        // Simple formula: attack power minus the target's defense
+        let damage = self.power(game) - target.defense(game);
+        if damage > 0 {
+            game.messages.add(
+                format!("{} attacks {} for {} hit points.", self.name, target.name, damage),
+                WHITE,
+            );
+            if let Some(xp) = target.take_damage(damage, game) {
+                self.gain_xp(xp);
+            }
+        } else {
+            game.messages.add(
+                format!("{} attacks {} but it has no effect!", self.name, target.name),
+                WHITE,
+            );
+        }
+    }
+
+    pub fn draw(&self, con: &mut dyn Canvas, camera: &Camera) {
+        if let Some((x, y)) = camera.map_to_screen(self.x, self.y) {
+            con.put_glyph(x, y, self.glyph, self.color);
+        }
+    }
+}
+
+
+// Combat-related properties, for anything that can attack or be attacked
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+struct Fighter {
+    base_max_hp: i32,
+    hp: i32,
+    base_defense: i32,
+    base_power: i32,
+    // Earned so far for the player, awarded on death for monsters
+    xp: i32,
+    on_death: DeathCallback,
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+enum DeathCallback {
+    Player,
+    Monster,
+}
+
+
+impl DeathCallback {
+    fn callback(self, object: &mut Object, game: &mut Game) {
+        let callback: fn(&mut Object, &mut Game) = match self {
+            DeathCallback::Player => player_death,
+            DeathCallback::Monster => monster_death,
+        };
+        callback(object, game);
+    }
+}
+
+
+fn player_death(pc: &mut Object, game: &mut Game) {
+    // Game over: the player turns into a corpse
+    game.messages.add("You died!", RED);
+    pc.glyph = '%';
+    pc.color = DARK_RED;
+}
+
+fn monster_death(monster: &mut Object, game: &mut Game) {
+    // Leaves a corpse that can't attack, be attacked or get in the way
+    game.messages.add(
+        format!(
+            "{} is dead! You gain {} experience points.",
+            monster.name,
+            monster.fighter.map_or(0, |f| f.xp)
+        ),
+        ORANGE,
+    );
+    monster.glyph = '%';
+    monster.color = DARK_RED;
+    monster.blocks = false;
+    monster.fighter = None;
+    monster.ai = None;
+    monster.name = format!("remains of {}", monster.name);
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+enum Stairs {
+    Up,
+    Down,
+}
+
+
+fn make_stairs(x: i32, y: i32, direction: Stairs) -> Object {
+    let (glyph, name) = match direction {
+        Stairs::Up => ('<', "stairs up"),
+        Stairs::Down => ('>', "stairs down"),
+    };
+    let mut stairs = Object::new(x, y, glyph, name, WHITE, false);
+    // Once found, stairs stay on screen even out of view
+    stairs.always_visible = true;
+    stairs.stairs = Some(direction);
+    stairs
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+enum Item {
+    Heal,
+    Lightning,
+    Fireball,
+    Confuse,
+    Equipment,
+}
+
+
+// Where a piece of equipment is worn, and how many of them fit there
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+enum Slot {
+    Weapon,
+    Shield,
+    Armour,
+    Ring,
+}
+
+
+impl Slot {
+    pub fn capacity(self) -> usize {
+        match self {
+            Slot::Ring => 2,
+            _ => 1,
+        }
+    }
+}
+
+
+impl fmt::Display for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Slot::Weapon => "weapon hand",
+            Slot::Shield => "shield arm",
+            Slot::Armour => "body",
+            Slot::Ring => "finger",
+        };
+        f.write_str(name)
+    }
+}
+
+
+// An object that can be equipped, yielding bonuses
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+struct Equipment {
+    slot: Slot,
+    equipped: bool,
+    power_bonus: i32,
+    defense_bonus: i32,
+    max_hp_bonus: i32,
+}
+
+
+fn make_equipment(
+    x: i32, y: i32, glyph: char, name: &str, color: Color, equipment: Equipment
+) -> Object {
+    let mut object = Object::new(x, y, glyph, name, color, false);
+    object.item = Some(Item::Equipment);
+    object.equipment = Some(equipment);
+    object
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum UseResult {
+    UsedUp,
+    UsedAndKept,
+    Cancelled,
+}
+
+fn toggle_equipment<T: Terminal>(
+    inventory_id: usize, _ui: &mut Ui<T>, game: &mut Game, objects: &mut [Object]
+) -> UseResult {
+    let equipment = match game.inventory[inventory_id].equipment {
+        Some(equipment) => equipment,
+        None => return UseResult::Cancelled,
+    };
+
+    if equipment.equipped {
+        game.inventory[inventory_id].dequip(&mut game.messages);
+    } else {
+        // Make room in a full slot by taking off whichever item worn there
+        // comes first in the inventory
+        let worn_in_slot: Vec<_> = game
+            .inventory
+            .iter()
+            .enumerate()
+            .filter(|(_, item)| {
+                item.equipment
+                    .is_some_and(|e| e.equipped && e.slot == equipment.slot)
+            })
+            .map(|(id, _)| id)
+            .collect();
+        if worn_in_slot.len() >= equipment.slot.capacity() {
+            game.inventory[worn_in_slot[0]].dequip(&mut game.messages);
+        }
+        game.inventory[inventory_id].equip(&mut game.messages);
+    }
+
+    objects[PLAYER].clamp_hp(game);
+    UseResult::UsedAndKept
+}
+
+
+fn use_item<T: Terminal>(
+    inventory_id: usize, ui: &mut Ui<T>, game: &mut Game, objects: &mut [Object]
+) -> UseResult {
+    use Item::*;
+
+    let item = match game.inventory[inventory_id].item {
+        Some(item) => item,
+        None => {
+            let name = game.inventory[inventory_id].name.clone();
+            game.messages.add(format!("The {} cannot be used.", name), WHITE);
+            return UseResult::Cancelled;
+        }
+    };
+
+    let on_use = match item {
+        Heal => cast_heal,
+        Lightning => cast_lightning,
+        Fireball => cast_fireball,
+        Confuse => cast_confuse,
+        Equipment => toggle_equipment,
+    };
+
+    // Only consumed if the effect actually happened
+    let result = on_use(inventory_id, ui, game, objects);
+    if result == UseResult::UsedUp {
+        game.inventory.remove(inventory_id);
+    }
+    result
+}
+
+fn cast_heal<T: Terminal>(
+    _inventory_id: usize, _ui: &mut Ui<T>, game: &mut Game, objects: &mut [Object]
+) -> UseResult {
+    if let Some(fighter) = objects[PLAYER].fighter {
+        if fighter.hp == objects[PLAYER].max_hp(game) {
+            game.messages.add("You are already at full health.", RED);
+            return UseResult::Cancelled;
+        }
+        game.messages.add("Your wounds start to feel better!", LIGHT_VIOLET);
+        objects[PLAYER].heal(HEAL_AMOUNT, game);
+        return UseResult::UsedUp;
+    }
+    UseResult::Cancelled
+}
+
+fn cast_lightning<T: Terminal>(
+    _inventory_id: usize, _ui: &mut Ui<T>, game: &mut Game, objects: &mut [Object]
+) -> UseResult {
+    // Zap the closest enemy in range
+    match closest_monster(LIGHTNING_RANGE, game, objects) {
+        Some(monster_id) => {
+            game.messages.add(
+                format!(
+                    "A lightning bolt strikes the {} with a loud thunder! \
+                     The damage is {} hit points.",
+                    objects[monster_id].name, LIGHTNING_DAMAGE
+                ),
+                LIGHT_BLUE,
+            );
+            if let Some(xp) = objects[monster_id].take_damage(LIGHTNING_DAMAGE, game) {
+                objects[PLAYER].gain_xp(xp);
+            }
+            UseResult::UsedUp
+        },
+        None => {
+            game.messages.add("No enemy is close enough to strike.", RED);
+            UseResult::Cancelled
+        },
+    }
+}
+
+fn cast_fireball<T: Terminal>(
+    _inventory_id: usize, ui: &mut Ui<T>, game: &mut Game, objects: &mut [Object]
+) -> UseResult {
+    game.messages.add("Choose a target tile for the fireball.", LIGHT_CYAN);
+    let (x, y) = match target_tile(ui, game, objects, None) {
+        Some(tile_pos) => tile_pos,
+        None => return UseResult::Cancelled,
+    };
+    game.messages.add(
+        format!("The fireball explodes, burning everything within {} tiles!", FIREBALL_RADIUS),
+        ORANGE,
+    );
+
+    // Burns everything caught in the blast, the player included
+    let mut xp_to_gain = 0;
+    for (id, object) in objects.iter_mut().enumerate() {
+        if object.distance(x, y) <= FIREBALL_RADIUS && object.fighter.is_some() {
+            game.messages.add(
+                format!("The {} gets burned for {} hit points.", object.name, FIREBALL_DAMAGE),
+                ORANGE,
+            );
+            if let Some(xp) = object.take_damage(FIREBALL_DAMAGE, game) {
+                // No reward for blowing yourself up
+                if id != PLAYER {
+                    xp_to_gain += xp;
+                }
+            }
+        }
+    }
+    objects[PLAYER].gain_xp(xp_to_gain);
+    UseResult::UsedUp
+}
+
+fn cast_confuse<T: Terminal>(
+    _inventory_id: usize, ui: &mut Ui<T>, game: &mut Game, objects: &mut [Object]
+) -> UseResult {
+    game.messages.add("Choose an enemy to confuse.", LIGHT_CYAN);
+    let monster_id = match target_monster(ui, game, objects, Some(CONFUSE_RANGE)) {
+        Some(monster_id) => monster_id,
+        None => return UseResult::Cancelled,
+    };
+
+    // Swap in a confused AI that hands back the old one when it wears off
+    let old_ai = objects[monster_id].ai.take().unwrap_or(Ai::Basic);
+    objects[monster_id].ai = Some(Ai::Confused {
+        previous_ai: Box::new(old_ai),
+        num_turns: CONFUSE_NUM_TURNS,
+    });
+    game.messages.add(
+        format!(
+            "The eyes of the {} look vacant, as it starts to stumble around!",
+            objects[monster_id].name
+        ),
+        LIGHT_GREEN,
+    );
+    UseResult::UsedUp
+}
+
+// The nearest monster the player can see, within range
+fn closest_monster(max_range: f32, game: &Game, objects: &[Object]) -> Option<usize> {
+    let mut closest_enemy = None;
+    let mut closest_dist = max_range + 1.0;
+
+    for (id, object) in objects.iter().enumerate() {
+        if id != PLAYER
+            && object.fighter.is_some()
+            && object.ai.is_some()
+            && game.fov.is_in_fov(object.x, object.y)
+        {
+            let dist = objects[PLAYER].distance_to(object);
+            if dist < closest_dist {
+                closest_enemy = Some(id);
+                closest_dist = dist;
+            }
+        }
+    }
+    closest_enemy
+}
+
+
+// Returns whether the item made it into the inventory
+fn pick_item_up(object_id: usize, game: &mut Game, objects: &mut Vec<Object>) -> bool {
+    if game.inventory.len() >= INVENTORY_SIZE {
+        game.messages.add(
+            format!("Your inventory is full, cannot pick up {}.", objects[object_id].name),
+            RED,
+        );
+        false
+    } else {
+        let item = objects.swap_remove(object_id);
+        game.messages.add(format!("You picked up a {}!", item.name), GREEN);
+        game.inventory.push(item);
+        true
+    }
+}
+
+fn drop_item(inventory_id: usize, game: &mut Game, objects: &mut Vec<Object>) {
+    let mut item = game.inventory.remove(inventory_id);
+    item.dequip(&mut game.messages);
+    objects[PLAYER].clamp_hp(game);
+    item.x = objects[PLAYER].x;
+    item.y = objects[PLAYER].y;
+    game.messages.add(format!("You dropped a {}.", item.name), YELLOW);
+    objects.push(item);
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+enum Ai {
+    Basic,
+    Confused {
+        previous_ai: Box<Ai>,
+        num_turns: i32,
+    },
+}
+
+
+fn ai_take_turn(monster_id: usize, game: &mut Game, objects: &mut [Object]) {
+    use Ai::*;
+
+    // Each AI returns the AI the monster should have next turn
+    if let Some(ai) = objects[monster_id].ai.take() {
+        let new_ai = match ai {
+            Basic => ai_basic(monster_id, game, objects),
+            Confused { previous_ai, num_turns } => {
+                ai_confused(monster_id, game, objects, previous_ai, num_turns)
+            }
+        };
+        // Unless it died in the meantime
+        if objects[monster_id].alive {
+            objects[monster_id].ai = Some(new_ai);
+        }
+    }
+}
+
+fn ai_basic(monster_id: usize, game: &mut Game, objects: &mut [Object]) -> Ai {
+    // A monster acts only when the player can see it (and so it can see the player)
+    let (monster_x, monster_y) = objects[monster_id].pos();
+    if !game.fov.is_in_fov(monster_x, monster_y) {
+        return Ai::Basic;
+    }
+
+    if objects[monster_id].distance_to(&objects[PLAYER]) >= 2.0 {
+        let (pc_x, pc_y) = objects[PLAYER].pos();
+        move_towards(monster_id, pc_x, pc_y, game, objects);
+    } else if objects[PLAYER].alive {
+        let (monster, pc) = mut_two(monster_id, PLAYER, objects);
+        monster.attack(pc, game);
+    }
+    Ai::Basic
+}
+
+fn ai_confused(
+    monster_id: usize,
+    game: &mut Game,
+    objects: &mut [Object],
+    previous_ai: Box<Ai>,
+    num_turns: i32,
+) -> Ai {
+    if num_turns >= 0 {
+        // Still confused: stagger in a random direction
+        let dx = game.rng.gen_range(-1..=1);
+        let dy = game.rng.gen_range(-1..=1);
+        move_by(monster_id, dx, dy, &game.map, objects);
+        Ai::Confused {
+            previous_ai,
+            num_turns: num_turns - 1,
         }
+    } else {
+        game.messages.add(
+            format!("The {} is no longer confused!", objects[monster_id].name),
+            RED,
+        );
+        *previous_ai
     }
+}
+
+fn move_towards(id: usize, target_x: i32, target_y: i32, game: &Game, objects: &mut [Object]) {
+    // Normalise the vector to the target to a single step, rounded to the grid
+    let dx = target_x - objects[id].x;
+    let dy = target_y - objects[id].y;
+    let distance = ((dx.pow(2) + dy.pow(2)) as f32).sqrt();
+
+    let dx = (dx as f32 / distance).round() as i32;
+    let dy = (dy as f32 / distance).round() as i32;
+    move_by(id, dx, dy, &game.map, objects);
+}
+
+
+// True if the tile is a wall or a blocking object stands on it
+fn is_blocked(x: i32, y: i32, map: &Map, objects: &[Object]) -> bool {
+    if map.is_blocked(x, y) {
+        return true;
+    }
+
+    objects
+        .iter()
+        .any(|object| object.blocks && object.pos() == (x, y))
+}
+
+fn move_by(id: usize, dx: i32, dy: i32, map: &Map, objects: &mut [Object]) {
+    let (x, y) = objects[id].pos();
+    if !is_blocked(x + dx, y + dy, map, objects) {
+        objects[id].x = x + dx;
+        objects[id].y = y + dy;
+    }
+}
+
+
+// Mutably borrows two *separate* elements from the given slice.
+// Panics when the indexes are equal or out of bounds.
+fn mut_two<T>(first_index: usize, second_index: usize, items: &mut [T]) -> (&mut T, &mut T) {
+    assert!(first_index != second_index);
+    let split_at_index = cmp::max(first_index, second_index);
+    let (first_slice, second_slice) = items.split_at_mut(split_at_index);
+    if first_index < second_index {
+        (&mut first_slice[first_index], &mut second_slice[0])
+    } else {
+        (&mut second_slice[0], &mut first_slice[second_index])
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PlayerAction {
+    TookTurn,
+    DidntTakeTurn,
+    Exit,
+}
+
+
+fn player_move_or_attack(dx: i32, dy: i32, game: &mut Game, objects: &mut [Object]) {
+    let x = objects[PLAYER].x + dx;
+    let y = objects[PLAYER].y + dy;
+
+    // Anything with a fighter component in the way gets attacked instead
+    let target_id = objects
+        .iter()
+        .position(|object| object.fighter.is_some() && object.pos() == (x, y));
+
+    match target_id {
+        Some(target_id) => {
+            let (pc, target) = mut_two(PLAYER, target_id, objects);
+            pc.attack(target, game);
+        },
+        None => move_by(PLAYER, dx, dy, &game.map, objects),
+    }
+}
+
+
+// Everything that happens once the player has used up a turn
+fn take_game_turn(game: &mut Game, objects: &mut [Object]) {
+    for id in 0..objects.len() {
+        if objects[id].ai.is_some() {
+            ai_take_turn(id, game, objects);
+        }
+    }
+}
+
+// The player's side of a turn spent waiting
+fn wait_in_place(game: &mut Game, objects: &mut [Object]) {
+    game.turns_waited += 1;
+    if game.turns_waited.is_multiple_of(REGEN_INTERVAL) {
+        objects[PLAYER].heal(1, game);
+    }
+}
+
+fn monster_in_view(game: &Game, objects: &[Object]) -> Option<usize> {
+    objects.iter().enumerate().position(|(id, object)| {
+        id != PLAYER && object.ai.is_some() && game.fov.is_in_fov(object.x, object.y)
+    })
+}
 
-    pub fn draw(&self, con: &mut dyn Console) {
-        con.set_default_foreground(self.color);
-        con.put_char(self.x, self.y, self.glyph, BackgroundFlag::None);
+// Waits turn after turn until fully healed, stopping early as soon as a
+// monster shows up. Monsters out of view don't move (see ai_basic), so in
+// practice only a confused one can stumble into view and cut a rest short.
+fn rest(game: &mut Game, objects: &mut [Object]) {
+    if let Some(id) = monster_in_view(game, objects) {
+        let name = objects[id].name.clone();
+        game.messages.add(format!("You can't rest with the {} in view.", name), RED);
+        return;
+    }
+    let hp = |objects: &[Object]| objects[PLAYER].fighter.map_or(0, |f| f.hp);
+    if hp(objects) >= objects[PLAYER].max_hp(game) {
+        game.messages.add("You are already at full health.", WHITE);
+        return;
+    }
+
+    game.messages.add("You rest for a while...", LIGHT_GREY);
+    while objects[PLAYER].alive && hp(objects) < objects[PLAYER].max_hp(game) {
+        wait_in_place(game, objects);
+        take_game_turn(game, objects);
+        if let Some(id) = monster_in_view(game, objects) {
+            let name = objects[id].name.clone();
+            game.messages.add(format!("You stop resting as the {} comes into view.", name), RED);
+            return;
+        }
+    }
+    if objects[PLAYER].alive {
+        game.messages.add("You feel fully rested.", LIGHT_GREY);
     }
 }
 
 
-#[derive(Clone, Copy,Debug)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 struct Tile {
     blocked: bool,
     block_sight: bool,
+    explored: bool,
 }
 
 
@@ -63,6 +940,7 @@ impl Tile {
         Tile {
             blocked: false,
             block_sight: false,
+            explored: false,
         }
     }
 
@@ -70,6 +948,7 @@ impl Tile {
         Tile {
             blocked: true,
             block_sight: true,
+            explored: false,
         }
     }
 }
@@ -92,157 +971,2011 @@ impl Rect {
             y2: y + h,
         }
     }
+
+    pub fn center(&self) -> (i32, i32) {
+        let center_x = (self.x1 + self.x2) / 2;
+        let center_y = (self.y1 + self.y2) / 2;
+        (center_x, center_y)
+    }
+
+    pub fn intersects_with(&self, other: &Rect) -> bool {
+        // True if this rectangle overlaps another one
+        (self.x1 <= other.x2)
+            && (self.x2 >= other.x1)
+            && (self.y1 <= other.y2)
+            && (self.y2 >= other.y1)
+    }
 }
 
 
 fn create_room(room: Rect, map: &mut Map) {
     for x in (room.x1 + 1)..room.x2 {
         for y in (room.y1 + 1)..room.y2 {
-            map[x as usize][y as usize] = Tile::empty();
+            map.set(x, y, Tile::empty());
         }
     }
 }
 
 fn create_h_tunnel(x1: i32, x2: i32, y: i32, map: &mut Map) {
     for x in cmp::min(x1, x2)..(cmp::max(x1, x2) + 1) {
-        map[x as usize][y as usize] = Tile::empty();
+        map.set(x, y, Tile::empty());
     }
 }
 
 fn create_v_tunnel(y1: i32, y2: i32, x: i32, map: &mut Map) {
     for y in cmp::min(y1, y2)..(cmp::max(y1, y2) + 1) {
-        map[x as usize][y as usize] = Tile::empty();
+        map.set(x, y, Tile::empty());
     }
 }
 
+fn create_l_tunnel(from: (i32, i32), to: (i32, i32), map: &mut Map, rng: &mut GameRng) {
+    let (x1, y1) = from;
+    let (x2, y2) = to;
+    // Randomly pick which leg of the L comes first
+    if rng.gen() {
+        create_h_tunnel(x1, x2, y1, map);
+        create_v_tunnel(y1, y2, x2, map);
+    } else {
+        create_v_tunnel(y1, y2, x1, map);
+        create_h_tunnel(x1, x2, y2, map);
+    }
+}
 
-type Map = Vec<Vec<Tile>>;
 
-struct Game {
-    map: Map,
+// Tiles stored row by row in one contiguous buffer
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Map {
+    width: i32,
+    height: i32,
+    tiles: Vec<Tile>,
 }
 
 
-fn make_map() -> Map {
-    let mut map = vec![
-        vec![
-            Tile::wall(); MAP_HEIGHT as usize
-        ]; MAP_WIDTH as usize
-    ];
+impl Map {
+    pub fn new(width: i32, height: i32, fill: Tile) -> Self {
+        Map {
+            width,
+            height,
+            tiles: vec![fill; (width * height) as usize],
+        }
+    }
 
-    // Wall placement examples
-    // map [30][22] = Tile::wall();
-    // map [50][22] = Tile::wall();
+    pub fn in_bounds(&self, x: i32, y: i32) -> bool {
+        x >= 0 && y >= 0 && x < self.width && y < self.height
+    }
 
-    // Room placement examples
-    let room1 = Rect::new(20, 15, 10, 15);
-    let room2 = Rect::new(50, 15, 10, 15);
-    create_room(room1, &mut map);
-    create_room(room2, &mut map);
-    create_h_tunnel(25, 55, 23, &mut map);
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        if self.in_bounds(x, y) {
+            Some((y * self.width + x) as usize)
+        } else {
+            None
+        }
+    }
 
-    map
-}
+    pub fn get(&self, x: i32, y: i32) -> Option<&Tile> {
+        self.index(x, y).map(|i| &self.tiles[i])
+    }
 
+    pub fn get_mut(&mut self, x: i32, y: i32) -> Option<&mut Tile> {
+        self.index(x, y).map(move |i| &mut self.tiles[i])
+    }
 
-fn handle_keys(tcod: &mut Tcod, pc: &mut Object, game: &Game) -> bool {
-    use tcod::input::Key;
-    use tcod::input::KeyCode::*;
+    // Replaces a tile; positions off the map are ignored
+    pub fn set(&mut self, x: i32, y: i32, tile: Tile) {
+        if let Some(old_tile) = self.get_mut(x, y) {
+            *old_tile = tile;
+        }
+    }
 
-    let key = tcod.root.wait_for_keypress(true);
-    match key {
-        // Window
-        Key { code: Enter, alt: true, .. } => {
-            let is_fullscreen = tcod.root.is_fullscreen();
-            tcod.root.set_fullscreen(!is_fullscreen);
-        },
-        Key { code: Escape, .. } => return true,
+    // Anything off the map counts as solid wall
+    pub fn is_blocked(&self, x: i32, y: i32) -> bool {
+        self.get(x, y).is_none_or(|tile| tile.blocked)
+    }
 
-        // Movement
-        Key { code: Up, .. } => pc.move_by(0, -1, game,),
-        Key { code: Down , .. } => pc.move_by(0, 1, game,),
-        Key { code: Left, .. } => pc.move_by(-1, 0, game,),
-        Key { code: Right , ..} => pc.move_by(1, 0, game,),
+    pub fn blocks_sight(&self, x: i32, y: i32) -> bool {
+        self.get(x, y).is_none_or(|tile| tile.block_sight)
+    }
 
-        // Default (all other keys)
-        _ => {}
+    // Every position on the map, row by row
+    pub fn positions(&self) -> impl Iterator<Item = (i32, i32)> {
+        let width = self.width;
+        (0..self.height).flat_map(move |y| (0..width).map(move |x| (x, y)))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = ((i32, i32), &Tile)> {
+        self.positions().zip(self.tiles.iter())
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = ((i32, i32), &mut Tile)> {
+        self.positions().zip(self.tiles.iter_mut())
     }
-    
-    false
 }
 
 
-fn render_all(tcod: &mut Tcod, game: &Game, objects: &[Object]) {
-    // Set background
-    for y in 0..MAP_HEIGHT {
-        for x in 0..MAP_WIDTH {
-            let wall = game.map[x as usize][y as usize].block_sight;
-            if wall {
-                tcod.con.set_char_background(
-                    x, y, COLOUR_DARK_WALL, BackgroundFlag::Set
-                );
-            } else {
-                tcod.con.set_char_background(
-                    x, y, COLOUR_DARK_GROUND, BackgroundFlag::Set
-                );
+// Every random roll comes from a stream of the game seed: one per depth for
+// generating levels (see level_rng) and stream 0, kept in Game, for play.
+// A seed therefore reproduces every level, however it was reached.
+type GameRng = ChaCha8Rng;
+
+// Which tiles the player can currently see, laid out like Map
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct FovMap {
+    width: i32,
+    height: i32,
+    visible: Vec<bool>,
+}
+
+
+impl FovMap {
+    pub fn new(width: i32, height: i32) -> Self {
+        FovMap {
+            width,
+            height,
+            visible: vec![false; (width * height) as usize],
+        }
+    }
+
+    // Positions off the map are never in view
+    pub fn is_in_fov(&self, x: i32, y: i32) -> bool {
+        x >= 0 && y >= 0 && x < self.width && y < self.height
+            && self.visible[(y * self.width + x) as usize]
+    }
+
+    fn set_visible(&mut self, x: i32, y: i32) {
+        if x >= 0 && y >= 0 && x < self.width && y < self.height {
+            self.visible[(y * self.width + x) as usize] = true;
+        }
+    }
+}
+
+// Everything left behind on a level the player is not currently on
+#[derive(Serialize, Deserialize)]
+struct Level {
+    map: Map,
+    objects: Vec<Object>,
+}
+
+
+// The game log shown in the status panel, oldest first
+#[derive(Serialize, Deserialize)]
+struct Messages {
+    messages: Vec<(String, Color)>,
+}
+
+
+impl Messages {
+    pub fn new() -> Self {
+        Self { messages: vec![] }
+    }
+
+    pub fn add<T: Into<String>>(&mut self, message: T, color: Color) {
+        self.messages.push((message.into(), color));
+        if self.messages.len() > MAX_MESSAGES {
+            self.messages.remove(0);
+        }
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &(String, Color)> {
+        self.messages.iter()
+    }
+}
+
+
+#[derive(Serialize, Deserialize)]
+struct Game {
+    map: Map,
+    fov: FovMap,
+    messages: Messages,
+    inventory: Vec<Object>,
+    seed: u64,
+    // For everything random during play. Levels use their own, see level_rng.
+    rng: GameRng,
+    map_style: MapStyle,
+    map_size: (i32, i32),
+    depth: u32,
+    // Previously visited levels, by depth
+    levels: HashMap<u32, Level>,
+    turns_waited: u32,
+}
+
+
+// Each depth draws from its own stream of the game seed, so a level's layout
+// depends only on the seed and depth, not on what happened before reaching it
+fn level_rng(seed: u64, depth: u32) -> GameRng {
+    let mut rng = GameRng::seed_from_u64(seed);
+    rng.set_stream(depth as u64);
+    rng
+}
+
+
+// Moves the player to another depth: the current level is stored as is, and
+// the new one is restored if visited before or generated otherwise
+fn change_level(new_depth: u32, game: &mut Game, objects: &mut Vec<Object>) {
+    let going_down = new_depth > game.depth;
+
+    let map = mem::replace(&mut game.map, Map::new(0, 0, Tile::wall()));
+    let left_behind = objects.drain(PLAYER + 1..).collect();
+    game.levels.insert(game.depth, Level { map, objects: left_behind });
+    game.depth = new_depth;
+
+    match game.levels.remove(&new_depth) {
+        Some(level) => {
+            game.map = level.map;
+            objects.extend(level.objects);
+        },
+        None => {
+            let mut rng = level_rng(game.seed, new_depth);
+            let (width, height) = game.map_size;
+            game.map = make_map(objects, game.map_style, width, height, new_depth, &mut rng);
+        },
+    }
+
+    // Arrive on the staircase leading back the way the player came
+    let arrival = if going_down { Stairs::Up } else { Stairs::Down };
+    let arrival_pos = objects
+        .iter()
+        .find(|object| object.stairs == Some(arrival))
+        .map(|stairs| stairs.pos());
+    if let Some((x, y)) = arrival_pos {
+        objects[PLAYER].x = x;
+        objects[PLAYER].y = y;
+    }
+
+    game.fov = FovMap::new(game.map.width, game.map.height);
+    update_fov(game, &objects[PLAYER]);
+}
+
+
+fn take_stairs(direction: Stairs, game: &mut Game, objects: &mut Vec<Object>) -> PlayerAction {
+    let pc_pos = objects[PLAYER].pos();
+    let on_stairs = objects
+        .iter()
+        .any(|object| object.stairs == Some(direction) && object.pos() == pc_pos);
+    if !on_stairs {
+        let text = match direction {
+            Stairs::Down => "There are no stairs down here.",
+            Stairs::Up => "There are no stairs up here.",
+        };
+        game.messages.add(text, WHITE);
+        return PlayerAction::DidntTakeTurn;
+    }
+
+    match direction {
+        Stairs::Down => {
+            change_level(game.depth + 1, game, objects);
+            game.messages.add(format!("You descend to depth {}.", game.depth), VIOLET);
+        },
+        Stairs::Up => {
+            change_level(game.depth - 1, game, objects);
+            game.messages.add(format!("You climb back up to depth {}.", game.depth), VIOLET);
+        },
+    }
+    PlayerAction::TookTurn
+}
+
+
+fn compute_fov(map: &Map, origin_x: i32, origin_y: i32, radius: i32) -> FovMap {
+    let mut fov = FovMap::new(map.width, map.height);
+    fov.set_visible(origin_x, origin_y);
+
+    for &octant in FOV_OCTANTS.iter() {
+        cast_light(map, &mut fov, (origin_x, origin_y), radius, 1, 1.0, 0.0, octant);
+    }
+
+    fov
+}
+
+// Recursive shadowcasting over one octant: scans rows outwards from the
+// origin, lighting tiles between the start and end slopes and recursing
+// past every run of tiles that block sight
+#[allow(clippy::too_many_arguments)]
+fn cast_light(
+    map: &Map,
+    fov: &mut FovMap,
+    origin: (i32, i32),
+    radius: i32,
+    row: i32,
+    mut start: f64,
+    end: f64,
+    octant: (i32, i32, i32, i32),
+) {
+    if start < end {
+        return;
+    }
+
+    let (xx, xy, yx, yy) = octant;
+    let mut new_start = 0.0;
+
+    for distance in row..=radius {
+        let dy = -distance;
+        let mut blocked = false;
+
+        for dx in -distance..=0 {
+            let x = origin.0 + dx * xx + dy * xy;
+            let y = origin.1 + dx * yx + dy * yy;
+            let left_slope = (dx as f64 - 0.5) / (dy as f64 + 0.5);
+            let right_slope = (dx as f64 + 0.5) / (dy as f64 - 0.5);
+
+            if start < right_slope {
+                continue;
+            } else if end > left_slope {
+                break;
+            }
+
+            if dx * dx + dy * dy <= radius * radius {
+                fov.set_visible(x, y);
+            }
+
+            let opaque = map.blocks_sight(x, y);
+            if blocked {
+                if opaque {
+                    new_start = right_slope;
+                } else {
+                    blocked = false;
+                    start = new_start;
+                }
+            } else if opaque && distance < radius {
+                blocked = true;
+                cast_light(map, fov, origin, radius, distance + 1, start, left_slope, octant);
+                new_start = right_slope;
+            }
+        }
+
+        if blocked {
+            break;
+        }
+    }
+}
+
+
+fn update_fov(game: &mut Game, pc: &Object) {
+    game.fov = compute_fov(&game.map, pc.x, pc.y, TORCH_RADIUS);
+
+    // Remember everything that has been seen
+    for ((x, y), tile) in game.map.iter_mut() {
+        if game.fov.is_in_fov(x, y) {
+            tile.explored = true;
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+enum MapStyle {
+    Rooms,
+    Bsp,
+    Caves,
+}
+
+
+fn make_map(
+    objects: &mut Vec<Object>,
+    style: MapStyle,
+    width: i32,
+    height: i32,
+    depth: u32,
+    rng: &mut GameRng,
+) -> Map {
+    let mut map = Map::new(width, height, Tile::wall());
+
+    // Player starts in the first room and the way down is in the last one.
+    // In caves, the way down is as far from the start as the cave goes.
+    let (start, exit) = match style {
+        MapStyle::Rooms => {
+            let rooms = place_random_rooms(&mut map, rng);
+            (rooms[0].center(), rooms[rooms.len() - 1].center())
+        },
+        MapStyle::Bsp => {
+            let mut rooms = vec![];
+            let area = Rect::new(0, 0, map.width - 1, map.height - 1);
+            // One extra split for every doubling of the default area
+            let mut bsp_depth = BSP_DEPTH;
+            let mut leaf_area = MAP_WIDTH * MAP_HEIGHT;
+            while leaf_area * 2 <= width * height {
+                bsp_depth += 1;
+                leaf_area *= 2;
+            }
+            split_bsp(area, bsp_depth, &mut map, &mut rooms, rng);
+            (rooms[0].center(), rooms[rooms.len() - 1].center())
+        },
+        MapStyle::Caves => {
+            let start = grow_caves(&mut map, rng);
+            (start, farthest_from(start, &map))
+        },
+    };
+
+    objects[PLAYER].x = start.0;
+    objects[PLAYER].y = start.1;
+
+    // The first level has no way back up
+    if depth > 1 {
+        objects.push(make_stairs(start.0, start.1, Stairs::Up));
+    }
+    objects.push(make_stairs(exit.0, exit.1, Stairs::Down));
+
+    place_monsters(&map, objects, rng);
+    place_items(&map, objects, rng);
+
+    map
+}
+
+
+// Only open floor is worth rolling for when placing things
+fn floor_tiles(map: &Map) -> Vec<(i32, i32)> {
+    map.iter()
+        .filter(|(_, tile)| !tile.blocked)
+        .map(|(pos, _)| pos)
+        .collect()
+}
+
+
+fn place_monsters(map: &Map, objects: &mut Vec<Object>, rng: &mut GameRng) {
+    let max_monsters = scale_to_map(MAX_MONSTERS, map);
+    let num_monsters = rng.gen_range(max_monsters / 2..=max_monsters);
+    let floor = floor_tiles(map);
+
+    for _ in 0..num_monsters {
+        let (x, y) = floor[rng.gen_range(0..floor.len())];
+
+        // Give up on this one rather than spawn on top of something else
+        if is_blocked(x, y, map, objects) {
+            continue;
+        }
+
+        // 80% orcs, 20% trolls
+        let mut monster = if rng.gen_bool(0.8) {
+            let mut orc = Object::new(x, y, 'o', "orc", DESATURATED_GREEN, true);
+            orc.fighter = Some(Fighter {
+                base_max_hp: 10,
+                hp: 10,
+                base_defense: 0,
+                base_power: 3,
+                xp: 35,
+                on_death: DeathCallback::Monster,
+            });
+            // Some orcs come armed
+            if rng.gen_bool(0.2) {
+                orc.worn.push(Equipment {
+                    slot: Slot::Weapon,
+                    equipped: true,
+                    power_bonus: 1,
+                    defense_bonus: 0,
+                    max_hp_bonus: 0,
+                });
+            }
+            orc
+        } else {
+            let mut troll = Object::new(x, y, 'T', "troll", DARKER_GREEN, true);
+            troll.fighter = Some(Fighter {
+                base_max_hp: 16,
+                hp: 16,
+                base_defense: 1,
+                base_power: 4,
+                xp: 100,
+                on_death: DeathCallback::Monster,
+            });
+            troll
+        };
+        monster.alive = true;
+        monster.ai = Some(Ai::Basic);
+        objects.push(monster);
+    }
+}
+
+
+// Scales a count tuned for the default map size to this map's area
+fn scale_to_map(count: i32, map: &Map) -> i32 {
+    cmp::max(1, count * map.width * map.height / (MAP_WIDTH * MAP_HEIGHT))
+}
+
+
+fn place_items(map: &Map, objects: &mut Vec<Object>, rng: &mut GameRng) {
+    let max_items = scale_to_map(MAX_ITEMS, map);
+    let num_items = rng.gen_range(0..=max_items);
+    let floor = floor_tiles(map);
+
+    for _ in 0..num_items {
+        let (x, y) = floor[rng.gen_range(0..floor.len())];
+
+        // Items don't block, so check for anything at all already there
+        if objects.iter().any(|object| object.pos() == (x, y)) {
+            continue;
+        }
+
+        // Mostly potions and scrolls, with the odd piece of equipment
+        let roll = rng.gen_range(0..100);
+        let mut item = if roll < 50 {
+            let mut potion = Object::new(x, y, '!', "healing potion", VIOLET, false);
+            potion.item = Some(Item::Heal);
+            potion
+        } else if roll < 60 {
+            let mut scroll = Object::new(x, y, '#', "scroll of lightning bolt", LIGHT_YELLOW, false);
+            scroll.item = Some(Item::Lightning);
+            scroll
+        } else if roll < 70 {
+            let mut scroll = Object::new(x, y, '#', "scroll of fireball", LIGHT_YELLOW, false);
+            scroll.item = Some(Item::Fireball);
+            scroll
+        } else if roll < 80 {
+            let mut scroll = Object::new(x, y, '#', "scroll of confusion", LIGHT_YELLOW, false);
+            scroll.item = Some(Item::Confuse);
+            scroll
+        } else if roll < 85 {
+            make_equipment(x, y, '/', "sword", SKY, Equipment {
+                slot: Slot::Weapon,
+                equipped: false,
+                power_bonus: 3,
+                defense_bonus: 0,
+                max_hp_bonus: 0,
+            })
+        } else if roll < 90 {
+            make_equipment(x, y, '[', "shield", DARKER_ORANGE, Equipment {
+                slot: Slot::Shield,
+                equipped: false,
+                power_bonus: 0,
+                defense_bonus: 1,
+                max_hp_bonus: 0,
+            })
+        } else if roll < 94 {
+            make_equipment(x, y, ']', "chain mail", LIGHT_GREY, Equipment {
+                slot: Slot::Armour,
+                equipped: false,
+                power_bonus: 0,
+                defense_bonus: 2,
+                max_hp_bonus: 0,
+            })
+        } else if roll < 97 {
+            make_equipment(x, y, '=', "ring of vitality", GOLD, Equipment {
+                slot: Slot::Ring,
+                equipped: false,
+                power_bonus: 0,
+                defense_bonus: 0,
+                max_hp_bonus: 10,
+            })
+        } else {
+            make_equipment(x, y, '=', "ring of strength", GOLD, Equipment {
+                slot: Slot::Ring,
+                equipped: false,
+                power_bonus: 1,
+                defense_bonus: 0,
+                max_hp_bonus: 0,
+            })
+        };
+        item.always_visible = true;
+        objects.push(item);
+    }
+}
+
+
+fn place_random_rooms(map: &mut Map, rng: &mut GameRng) -> Vec<Rect> {
+    let mut rooms: Vec<Rect> = vec![];
+
+    for _ in 0..scale_to_map(MAX_ROOMS, map) {
+        // Random size and position, kept inside the map bounds
+        let w = rng.gen_range(ROOM_MIN_SIZE..=ROOM_MAX_SIZE);
+        let h = rng.gen_range(ROOM_MIN_SIZE..=ROOM_MAX_SIZE);
+        let x = rng.gen_range(0..map.width - w);
+        let y = rng.gen_range(0..map.height - h);
+
+        let new_room = Rect::new(x, y, w, h);
+
+        // Reject rooms that overlap one already placed
+        let failed = rooms
+            .iter()
+            .any(|other_room| new_room.intersects_with(other_room));
+        if failed {
+            continue;
+        }
+
+        create_room(new_room, map);
+
+        // Join to the previous room
+        if let Some(prev_room) = rooms.last() {
+            create_l_tunnel(prev_room.center(), new_room.center(), map, rng);
+        }
+
+        rooms.push(new_room);
+    }
+
+    rooms
+}
+
+
+// Recursively halves `area` until `depth` runs out or the halves would be
+// too small, carves one room per leaf and joins each pair of siblings.
+// Returns the centre of a room inside `area` for the parent to connect to.
+fn split_bsp(
+    area: Rect, depth: i32, map: &mut Map, rooms: &mut Vec<Rect>, rng: &mut GameRng
+) -> (i32, i32) {
+    let w = area.x2 - area.x1;
+    let h = area.y2 - area.y1;
+    let can_split_x = w >= 2 * BSP_MIN_LEAF_SIZE;
+    let can_split_y = h >= 2 * BSP_MIN_LEAF_SIZE;
+
+    if depth == 0 || !(can_split_x || can_split_y) {
+        // Leaf: a random room that fits entirely inside it
+        let room_w = rng.gen_range(ROOM_MIN_SIZE..=cmp::min(ROOM_MAX_SIZE, w));
+        let room_h = rng.gen_range(ROOM_MIN_SIZE..=cmp::min(ROOM_MAX_SIZE, h));
+        let x = area.x1 + rng.gen_range(0..=w - room_w);
+        let y = area.y1 + rng.gen_range(0..=h - room_h);
+
+        let room = Rect::new(x, y, room_w, room_h);
+        create_room(room, map);
+        rooms.push(room);
+        return room.center();
+    }
+
+    // Prefer cutting across the longer side, so leaves stay roughly square
+    let split_x = if can_split_x && can_split_y {
+        if w > h * 5 / 4 {
+            true
+        } else if h > w * 5 / 4 {
+            false
+        } else {
+            rng.gen()
+        }
+    } else {
+        can_split_x
+    };
+
+    let (first, second) = if split_x {
+        let cut = rng.gen_range(BSP_MIN_LEAF_SIZE..=w - BSP_MIN_LEAF_SIZE);
+        (
+            Rect::new(area.x1, area.y1, cut, h),
+            Rect::new(area.x1 + cut, area.y1, w - cut, h),
+        )
+    } else {
+        let cut = rng.gen_range(BSP_MIN_LEAF_SIZE..=h - BSP_MIN_LEAF_SIZE);
+        (
+            Rect::new(area.x1, area.y1, w, cut),
+            Rect::new(area.x1, area.y1 + cut, w, h - cut),
+        )
+    };
+
+    let first_center = split_bsp(first, depth - 1, map, rooms, rng);
+    let second_center = split_bsp(second, depth - 1, map, rooms, rng);
+    create_l_tunnel(first_center, second_center, map, rng);
+
+    if rng.gen() { first_center } else { second_center }
+}
+
+
+// Fills the map with random noise, smooths it into caves and then makes
+// sure every open tile left can be reached from the returned start tile
+fn grow_caves(map: &mut Map, rng: &mut GameRng) -> (i32, i32) {
+    // The outer border always stays wall
+    for x in 1..(map.width - 1) {
+        for y in 1..(map.height - 1) {
+            if !rng.gen_bool(CAVE_WALL_CHANCE) {
+                map.set(x, y, Tile::empty());
+            }
+        }
+    }
+
+    for _ in 0..CAVE_SMOOTH_PASSES {
+        let previous = map.clone();
+        for x in 1..(map.width - 1) {
+            for y in 1..(map.height - 1) {
+                let walls = count_wall_neighbours(x, y, &previous);
+                let wall = if previous.is_blocked(x, y) {
+                    walls >= CAVE_SURVIVAL_LIMIT
+                } else {
+                    walls >= CAVE_BIRTH_LIMIT
+                };
+                map.set(x, y, if wall { Tile::wall() } else { Tile::empty() });
+            }
+        }
+    }
+
+    let mut regions = find_regions(map);
+    if regions.is_empty() {
+        // Smoothing closed everything up: fall back to a single chamber
+        let room = Rect::new(map.width / 2 - 5, map.height / 2 - 5, 10, 10);
+        create_room(room, map);
+        return room.center();
+    }
+
+    // Keep the largest cave, then fill or connect every other pocket
+    regions.sort_by_key(|region| cmp::Reverse(region.len()));
+    let mut main_cave = regions.remove(0);
+    for region in regions {
+        if region.len() < CAVE_MIN_REGION_SIZE {
+            for &(x, y) in &region {
+                map.set(x, y, Tile::wall());
+            }
+        } else {
+            let (from, to) = closest_pair(&region, &main_cave);
+            create_l_tunnel(from, to, map, rng);
+            main_cave.extend(region);
+        }
+    }
+
+    main_cave[rng.gen_range(0..main_cave.len())]
+}
+
+fn count_wall_neighbours(x: i32, y: i32, map: &Map) -> usize {
+    let mut walls = 0;
+    for dx in -1..=1 {
+        for dy in -1..=1 {
+            if dx == 0 && dy == 0 {
+                continue;
+            }
+            if map.is_blocked(x + dx, y + dy) {
+                walls += 1;
+            }
+        }
+    }
+    walls
+}
+
+// The open tile with the longest walk from the start
+fn farthest_from(start: (i32, i32), map: &Map) -> (i32, i32) {
+    let mut seen = vec![false; map.tiles.len()];
+    let mut farthest = start;
+    let mut queue = VecDeque::new();
+    seen[(start.1 * map.width + start.0) as usize] = true;
+    queue.push_back(start);
+
+    // Breadth-first, so the last tile reached is the farthest away
+    while let Some((x, y)) = queue.pop_front() {
+        farthest = (x, y);
+        for (dx, dy) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+            let (nx, ny) = (x + dx, y + dy);
+            if map.is_blocked(nx, ny) {
+                continue;
+            }
+            let neighbour = (ny * map.width + nx) as usize;
+            if !seen[neighbour] {
+                seen[neighbour] = true;
+                queue.push_back((nx, ny));
+            }
+        }
+    }
+
+    farthest
+}
+
+// Flood fills every open tile, grouping them into orthogonally connected regions
+fn find_regions(map: &Map) -> Vec<Vec<(i32, i32)>> {
+    let mut seen = vec![false; map.tiles.len()];
+    let mut regions = vec![];
+
+    for (x, y) in map.positions() {
+        let index = (y * map.width + x) as usize;
+        if seen[index] || map.is_blocked(x, y) {
+            continue;
+        }
+
+        let mut region = vec![];
+        let mut queue = VecDeque::new();
+        seen[index] = true;
+        queue.push_back((x, y));
+        while let Some((cx, cy)) = queue.pop_front() {
+            region.push((cx, cy));
+            for (dx, dy) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+                let (nx, ny) = (cx + dx, cy + dy);
+                if map.is_blocked(nx, ny) {
+                    continue;
+                }
+                let neighbour = (ny * map.width + nx) as usize;
+                if !seen[neighbour] {
+                    seen[neighbour] = true;
+                    queue.push_back((nx, ny));
+                }
+            }
+        }
+        regions.push(region);
+    }
+
+    regions
+}
+
+fn closest_pair(a: &[(i32, i32)], b: &[(i32, i32)]) -> ((i32, i32), (i32, i32)) {
+    let mut best = (a[0], b[0]);
+    let mut best_distance = i32::MAX;
+    for &(ax, ay) in a {
+        for &(bx, by) in b {
+            let distance = (ax - bx).abs() + (ay - by).abs();
+            if distance < best_distance {
+                best_distance = distance;
+                best = ((ax, ay), (bx, by));
+            }
+        }
+    }
+    best
+}
+
+
+// Everything the player can ask for, whichever key they pressed to do it
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Action {
+    Move(i32, i32),
+    Wait,
+    Rest,
+    PickUp,
+    Descend,
+    Ascend,
+    Inventory,
+    Drop,
+    CharacterSheet,
+    Look,
+    ToggleFullscreen,
+    Quit,
+}
+
+
+// How each action is named in a key bindings file, with its default keys
+// The plain digits back up the numpad ones, as most terminals send the same
+// thing for both
+const DEFAULT_BINDINGS: &[(&str, Action, &[&str])] = &[
+    ("move_north", Action::Move(0, -1), &["up", "numpad8", "8"]),
+    ("move_south", Action::Move(0, 1), &["down", "numpad2", "2"]),
+    ("move_west", Action::Move(-1, 0), &["left", "numpad4", "4"]),
+    ("move_east", Action::Move(1, 0), &["right", "numpad6", "6"]),
+    ("move_north_west", Action::Move(-1, -1), &["home", "numpad7", "7", "y"]),
+    ("move_north_east", Action::Move(1, -1), &["pageup", "numpad9", "9", "u"]),
+    ("move_south_west", Action::Move(-1, 1), &["end", "numpad1", "1", "b"]),
+    ("move_south_east", Action::Move(1, 1), &["pagedown", "numpad3", "3", "n"]),
+    ("wait", Action::Wait, &[".", "numpad5", "5"]),
+    ("rest", Action::Rest, &["r"]),
+    ("pick_up", Action::PickUp, &["g"]),
+    ("descend", Action::Descend, &[">"]),
+    ("ascend", Action::Ascend, &["<"]),
+    ("inventory", Action::Inventory, &["i"]),
+    ("drop", Action::Drop, &["d"]),
+    ("character_sheet", Action::CharacterSheet, &["c"]),
+    ("look", Action::Look, &["l"]),
+    ("toggle_fullscreen", Action::ToggleFullscreen, &["alt+enter"]),
+    ("quit", Action::Quit, &["escape"]),
+];
+
+
+struct KeyBindings {
+    actions: HashMap<Key, Action>,
+}
+
+
+impl KeyBindings {
+    pub fn new() -> Self {
+        let mut actions = HashMap::new();
+        for &(_, action, keys) in DEFAULT_BINDINGS {
+            for key in keys {
+                actions.insert(key.parse().unwrap(), action);
+            }
+        }
+        KeyBindings { actions }
+    }
+
+    // Rebinds the actions named in a key bindings file, one per line:
+    //
+    //     move_north: k up
+    //
+    // The listed keys replace the action's defaults and are taken from
+    // whatever they were bound to before, which is an error if it leaves that
+    // action with no keys at all. An action listed with no keys is unbound on
+    // purpose. Lines starting with # are comments.
+    pub fn load(&mut self, text: &str) -> Result<(), String> {
+        let mut unbound = vec![];
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {}", number + 1, message);
+
+            let (name, keys) = line
+                .split_once(':')
+                .ok_or_else(|| error("expected '<action>: <keys>'".into()))?;
+            let action = DEFAULT_BINDINGS
+                .iter()
+                .find(|(action_name, _, _)| *action_name == name.trim())
+                .map(|&(_, action, _)| action)
+                .ok_or_else(|| error(format!("unknown action '{}'", name.trim())))?;
+
+            self.actions.retain(|_, bound| *bound != action);
+            for key in keys.split_whitespace() {
+                self.actions.insert(key.parse().map_err(error)?, action);
+            }
+            if keys.trim().is_empty() {
+                unbound.push(action);
+            }
+        }
+
+        for &(name, action, _) in DEFAULT_BINDINGS {
+            if !unbound.contains(&action) && !self.actions.values().any(|&bound| bound == action) {
+                return Err(format!(
+                    "'{}' has no keys left; bind it to another key, or list it with none \
+                     to leave it unbound",
+                    name
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn action(&self, key: Key) -> Option<Action> {
+        self.actions.get(&key).copied()
+    }
+}
+
+
+fn handle_keys<T: Terminal>(
+    ui: &mut Ui<T>, game: &mut Game, objects: &mut Vec<Object>
+) -> PlayerAction {
+    use Action::*;
+
+    let action = match ui.key.and_then(|key| ui.bindings.action(key)) {
+        Some(action) => action,
+        None => return PlayerAction::DidntTakeTurn,
+    };
+    let pc_alive = objects[PLAYER].alive;
+    match (action, pc_alive) {
+        // Window
+        (ToggleFullscreen, _) => {
+            ui.root.toggle_fullscreen();
+            PlayerAction::DidntTakeTurn
+        },
+        (Quit, _) => PlayerAction::Exit,
+
+        // Movement (only while alive)
+        (Move(dx, dy), true) => {
+            player_move_or_attack(dx, dy, game, objects);
+            PlayerAction::TookTurn
+        },
+        (Wait, true) => {
+            wait_in_place(game, objects);
+            PlayerAction::TookTurn
+        },
+        (Rest, true) => {
+            rest(game, objects);
+            PlayerAction::DidntTakeTurn
+        },
+
+        // Stairs
+        (Descend, true) => take_stairs(Stairs::Down, game, objects),
+        (Ascend, true) => take_stairs(Stairs::Up, game, objects),
+
+        // Items
+        (PickUp, true) => {
+            // Pick up an item underfoot
+            let item_id = objects
+                .iter()
+                .position(|object| object.pos() == objects[PLAYER].pos() && object.item.is_some());
+            match item_id {
+                Some(item_id) if pick_item_up(item_id, game, objects) => PlayerAction::TookTurn,
+                Some(_) => PlayerAction::DidntTakeTurn,
+                None => {
+                    game.messages.add("There is nothing here to pick up.", WHITE);
+                    PlayerAction::DidntTakeTurn
+                },
+            }
+        },
+        (Inventory, true) => {
+            let inventory_index = inventory_menu(
+                &game.inventory,
+                "Press the key next to an item to use it, or any other to cancel.\n",
+                &mut ui.root,
+            );
+            match inventory_index.map(|index| use_item(index, ui, game, objects)) {
+                Some(UseResult::UsedUp | UseResult::UsedAndKept) => PlayerAction::TookTurn,
+                _ => PlayerAction::DidntTakeTurn,
             }
+        },
+        (Drop, true) => {
+            let inventory_index = inventory_menu(
+                &game.inventory,
+                "Press the key next to an item to drop it, or any other to cancel.\n",
+                &mut ui.root,
+            );
+            match inventory_index {
+                Some(inventory_index) => {
+                    drop_item(inventory_index, game, objects);
+                    PlayerAction::TookTurn
+                },
+                None => PlayerAction::DidntTakeTurn,
+            }
+        },
+
+        // Character sheet
+        (CharacterSheet, _) => {
+            let pc = &objects[PLAYER];
+            let xp = pc.fighter.map_or(0, |f| f.xp);
+            let hp = pc.fighter.map_or(0, |f| f.hp);
+            let msg = format!(
+                "Character information\n\n\
+                 Level: {}\n\
+                 Experience: {}\n\
+                 Experience to level up: {}\n\n\
+                 Maximum HP: {}\n\
+                 HP: {}\n\
+                 Attack: {}\n\
+                 Defense: {}",
+                pc.level,
+                xp,
+                level_up_xp(pc.level) - xp,
+                pc.max_hp(game),
+                hp,
+                pc.power(game),
+                pc.defense(game),
+            );
+            msgbox(&msg, CHARACTER_SCREEN_WIDTH, &mut ui.root);
+            PlayerAction::DidntTakeTurn
+        },
+
+        // Look around with a cursor, without spending a turn
+        (Look, _) => {
+            target_tile(ui, game, objects, None);
+            PlayerAction::DidntTakeTurn
+        },
+
+        // Anything else needs a living player
+        _ => PlayerAction::DidntTakeTurn,
+    }
+}
+
+
+fn render_all<T: Terminal>(ui: &mut Ui<T>, game: &Game, objects: &[Object]) {
+    let (pc_x, pc_y) = objects[PLAYER].pos();
+    ui.camera.follow(pc_x, pc_y, &game.map);
+
+    // Set background
+    for screen_y in 0..VIEW_HEIGHT {
+        for screen_x in 0..VIEW_WIDTH {
+            let (x, y) = ui.camera.screen_to_map(screen_x, screen_y);
+            // Unexplored tiles (and anything off the map) are left black
+            let tile = match game.map.get(x, y) {
+                Some(tile) if tile.explored => tile,
+                _ => continue,
+            };
+
+            let visible = game.fov.is_in_fov(x, y);
+            let wall = tile.block_sight;
+            let colour = match (visible, wall) {
+                (false, true) => COLOUR_DARK_WALL,
+                (false, false) => COLOUR_DARK_GROUND,
+                (true, true) => COLOUR_LIGHT_WALL,
+                (true, false) => COLOUR_LIGHT_GROUND,
+            };
+            ui.con.set_bg(screen_x, screen_y, colour);
         }
     }
-    for object in objects {
-        object.draw(&mut tcod.con);
+
+    // Objects only show up while in view, with non-blocking ones (corpses)
+    // drawn first so anything standing on them stays visible
+    let mut to_draw: Vec<_> = objects
+        .iter()
+        .filter(|object| {
+            game.fov.is_in_fov(object.x, object.y)
+                || (object.always_visible
+                    && game.map.get(object.x, object.y).is_some_and(|tile| tile.explored))
+        })
+        .collect();
+    to_draw.sort_by_key(|object| object.blocks);
+    for object in to_draw {
+        object.draw(&mut ui.con, &ui.camera);
     }
 
     // Add sub-consoles into root
-    blit(
-        &tcod.con,
-        SCREEN_ORIGIN,
-        (MAP_WIDTH, MAP_HEIGHT),
-        &mut tcod.root,
-        SCREEN_ORIGIN,
-        OPAQUE,
-        OPAQUE,
+    ui.root.blit(&ui.con, SCREEN_ORIGIN, OPAQUE, OPAQUE);
+
+    // Status panel
+    ui.panel.clear();
+
+    // Names of whatever the mouse is over
+    if ui.mouse.cy < VIEW_HEIGHT {
+        let (x, y) = ui.camera.screen_to_map(ui.mouse.cx, ui.mouse.cy);
+        let names = get_names_under(x, y, game, objects);
+        ui.panel.print(1, 0, Align::Left, LIGHT_GREY, &names);
+    }
+
+    // Player stats
+    let hp = objects[PLAYER].fighter.map_or(0, |f| f.hp);
+    let max_hp = objects[PLAYER].max_hp(game);
+    render_bar(&mut ui.panel, 1, 1, BAR_WIDTH, "HP", hp, max_hp, LIGHT_RED, DARKER_RED);
+
+    // Depth, and the seed so any dungeon seen on screen can be regenerated
+    ui.panel.print(1, 2, Align::Left, LIGHT_GREY, &format!("Depth: {}", game.depth));
+    ui.panel.print(1, 3, Align::Left, LIGHT_GREY, &format!("Seed: {}", game.seed));
+
+    // Message log, newest at the bottom, wrapping long lines and scrolling
+    // older ones off the top
+    let mut y = MSG_HEIGHT;
+    for (message, color) in game.messages.iter().rev() {
+        let message_height = terminal::wrap_text(message, MSG_WIDTH).len() as i32;
+        y -= message_height;
+        if y < 0 {
+            break;
+        }
+        ui.panel.print_rect(MSG_X, y, MSG_WIDTH, *color, message);
+    }
+
+    ui.root.blit(&ui.panel, (0, PANEL_Y), OPAQUE, OPAQUE);
+}
+
+
+// Comma-separated names of the objects at a map position the player can
+// currently see
+fn get_names_under(x: i32, y: i32, game: &Game, objects: &[Object]) -> String {
+    if !game.fov.is_in_fov(x, y) {
+        return String::new();
+    }
+    let names: Vec<_> = objects
+        .iter()
+        .filter(|object| object.pos() == (x, y))
+        .map(|object| object.name.clone())
+        .collect();
+    names.join(", ")
+}
+
+
+// A horizontal bar (HP, experience, etc.) with its value printed over it
+#[allow(clippy::too_many_arguments)]
+fn render_bar(
+    panel: &mut dyn Canvas,
+    x: i32,
+    y: i32,
+    total_width: i32,
+    name: &str,
+    value: i32,
+    maximum: i32,
+    bar_color: Color,
+    back_color: Color,
+) {
+    let bar_width = if maximum > 0 {
+        (value.clamp(0, maximum) as f32 / maximum as f32 * total_width as f32) as i32
+    } else {
+        0
+    };
+
+    // The filled part, with the background showing through after it
+    for bar_x in x..x + total_width {
+        let color = if bar_x < x + bar_width { bar_color } else { back_color };
+        panel.set_bg(bar_x, y, color);
+    }
+
+    let text = format!("{}: {}/{}", name, value, maximum);
+    panel.print(x + total_width / 2, y, Align::Center, WHITE, &text);
+}
+
+
+// What goes to disk: the whole game plus the objects on the current level
+#[derive(Serialize)]
+struct SaveFile<'a> {
+    version: u32,
+    game: &'a Game,
+    objects: &'a [Object],
+}
+
+
+#[derive(Deserialize)]
+struct LoadedSave {
+    game: Game,
+    objects: Vec<Object>,
+}
+
+
+// Read first on its own, so an old save is reported as such rather than as
+// whatever field happens to fail to parse
+#[derive(Deserialize)]
+struct SaveHeader {
+    version: u32,
+}
+
+
+#[derive(Debug)]
+enum LoadError {
+    Io(io::Error),
+    Corrupt(serde_json::Error),
+    IncompatibleVersion(u32),
+}
+
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "could not read the save: {}", e),
+            LoadError::Corrupt(e) => write!(f, "the save is corrupt: {}", e),
+            LoadError::IncompatibleVersion(version) => write!(
+                f,
+                "the save is in format version {}, but this version of the game only reads {}",
+                version, SAVE_VERSION
+            ),
+        }
+    }
+}
+
+
+impl Error for LoadError {}
+
+
+// Writes to a temporary file first and then swaps it in, so being killed
+// halfway through leaves the previous save intact
+fn save_game(path: &Path, game: &Game, objects: &[Object]) -> Result<(), Box<dyn Error>> {
+    let save_file = SaveFile { version: SAVE_VERSION, game, objects };
+    let data = serde_json::to_string(&save_file)?;
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, data)?;
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+
+fn load_game(path: &Path) -> Result<(Game, Vec<Object>), LoadError> {
+    let data = fs::read_to_string(path).map_err(LoadError::Io)?;
+
+    let header: SaveHeader = serde_json::from_str(&data).map_err(LoadError::Corrupt)?;
+    if header.version != SAVE_VERSION {
+        return Err(LoadError::IncompatibleVersion(header.version));
+    }
+
+    let save: LoadedSave = serde_json::from_str(&data).map_err(LoadError::Corrupt)?;
+    Ok((save.game, save.objects))
+}
+
+
+fn new_game(options: &Options) -> (Game, Vec<Object>) {
+    let mut pc = Object::new(0, 0, '@', "player", WHITE, true);
+    pc.alive = true;
+    pc.uses_inventory = true;
+    pc.fighter = Some(Fighter {
+        base_max_hp: 30,
+        hp: 30,
+        base_defense: 2,
+        base_power: 3,
+        xp: 0,
+        on_death: DeathCallback::Player,
+    });
+
+    let mut objects = vec![pc];
+
+    // Without --seed, every new game gets a fresh dungeon
+    let seed = options.seed.unwrap_or_else(rand::random);
+
+    let mut rng = level_rng(seed, 1);
+    let (map_width, map_height) = options.map_size;
+    let map = make_map(&mut objects, options.map_style, map_width, map_height, 1, &mut rng);
+    let fov = FovMap::new(map.width, map.height);
+    let mut game = Game {
+        map,
+        fov,
+        messages: Messages::new(),
+        inventory: vec![],
+        seed,
+        // Levels start at depth 1, leaving stream 0 free for play
+        rng: level_rng(seed, 0),
+        map_style: options.map_style,
+        map_size: options.map_size,
+        depth: 1,
+        levels: HashMap::new(),
+        turns_waited: 0,
+    };
+    update_fov(&mut game, &objects[PLAYER]);
+
+    // Everyone starts out with a dagger in hand
+    let dagger = make_equipment(0, 0, '-', "dagger", SKY, Equipment {
+        slot: Slot::Weapon,
+        equipped: true,
+        power_bonus: 2,
+        defense_bonus: 0,
+        max_hp_bonus: 0,
+    });
+    game.inventory.push(dagger);
+
+    game.messages.add(
+        "Welcome, stranger! Find the stairs down, and mind the orcs.",
+        RED,
     );
+
+    (game, objects)
 }
 
 
-fn main() {
-    // Root console (window) properties
-    let root = Root::initializer()
-        .font("arial10x10.png", FontLayout::Tcod)
-        .font_type(FontType::Greyscale)
-        .size(SCREEN_WIDTH, SCREEN_HEIGHT)
-        .title("Rust-game")
-        .init();
+// Where the game is shown: a libtcod window, or the terminal it was started in
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Frontend {
+    #[cfg(feature = "tcod")]
+    Tcod,
+    Ansi,
+}
 
-    // Game-layer console properties
-    let con = Offscreen::new(MAP_WIDTH, MAP_HEIGHT);
 
-    let mut tcod = Tcod { root, con };
+#[cfg(feature = "tcod")]
+const DEFAULT_FRONTEND: Frontend = Frontend::Tcod;
+#[cfg(not(feature = "tcod"))]
+const DEFAULT_FRONTEND: Frontend = Frontend::Ansi;
+
+#[cfg(feature = "tcod")]
+const FRONTEND_NAMES: &str = "tcod|ansi";
+#[cfg(not(feature = "tcod"))]
+const FRONTEND_NAMES: &str = "ansi";
+
+
+struct Options {
+    seed: Option<u64>,
+    map_style: MapStyle,
+    map_size: (i32, i32),
+    continue_game: bool,
+    frontend: Frontend,
+    keys_file: Option<String>,
+    // Script of key presses to play without a window, printing the screen
+    headless: Option<String>,
+    // Scripted runs leave any real save file alone
+    save: bool,
+}
+
+
+fn parse_args() -> Options {
+    let mut options = Options {
+        seed: None,
+        map_style: MapStyle::Rooms,
+        map_size: (MAP_WIDTH, MAP_HEIGHT),
+        continue_game: false,
+        frontend: DEFAULT_FRONTEND,
+        keys_file: None,
+        headless: None,
+        save: true,
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--continue" => options.continue_game = true,
+            "--frontend" => {
+                let value = args.next().unwrap_or_default();
+                options.frontend = match value.as_str() {
+                    #[cfg(feature = "tcod")]
+                    "tcod" => Frontend::Tcod,
+                    "ansi" => Frontend::Ansi,
+                    _ => {
+                        eprintln!("Invalid frontend '{}': expected {}", value, FRONTEND_NAMES);
+                        process::exit(2);
+                    }
+                };
+            },
+            "--keys" => {
+                let value = args.next().unwrap_or_else(|| {
+                    eprintln!("Missing key bindings file for --keys");
+                    process::exit(2);
+                });
+                options.keys_file = Some(value);
+            },
+            "--headless" => {
+                let value = args.next().unwrap_or_else(|| {
+                    eprintln!("Missing script file for --headless");
+                    process::exit(2);
+                });
+                options.headless = Some(value);
+                options.save = false;
+            },
+            "--seed" => {
+                let value = args.next().unwrap_or_default();
+                options.seed = Some(value.parse().unwrap_or_else(|_| {
+                    eprintln!("Invalid seed '{}': expected a whole number", value);
+                    process::exit(2);
+                }));
+            },
+            "--map" => {
+                let value = args.next().unwrap_or_default();
+                options.map_style = match value.as_str() {
+                    "rooms" => MapStyle::Rooms,
+                    "bsp" => MapStyle::Bsp,
+                    "caves" => MapStyle::Caves,
+                    _ => {
+                        eprintln!("Invalid map style '{}': expected rooms, bsp or caves", value);
+                        process::exit(2);
+                    }
+                };
+            },
+            "--size" => {
+                let value = args.next().unwrap_or_default();
+                options.map_size = parse_map_size(&value).unwrap_or_else(|| {
+                    eprintln!(
+                        "Invalid map size '{}': expected WIDTHxHEIGHT, each from {} to {}",
+                        value, MIN_MAP_SIZE, MAX_MAP_SIZE
+                    );
+                    process::exit(2);
+                });
+            },
+            _ => {
+                eprintln!("Unknown argument '{}'", arg);
+                eprintln!(
+                    "Usage: rust-game [--continue] [--frontend <{}>] [--keys <file>] \
+                     [--headless <script>] \
+                     [--seed <number>] [--map <rooms|bsp|caves>] [--size <WxH>]",
+                    FRONTEND_NAMES
+                );
+                process::exit(2);
+            }
+        }
+    }
+
+    options
+}
+
+
+// Parses "WIDTHxHEIGHT", e.g. "200x200"
+fn parse_map_size(value: &str) -> Option<(i32, i32)> {
+    let (width, height) = value.split_once('x')?;
+    let width: i32 = width.parse().ok()?;
+    let height: i32 = height.parse().ok()?;
+    let in_range = |size| (MIN_MAP_SIZE..=MAX_MAP_SIZE).contains(&size);
+    if !in_range(width) || !in_range(height) {
+        return None;
+    }
+    Some((width, height))
+}
+
+
+// Lettered list of options in a box in the middle of the screen. Returns the
+// chosen index, or None for any other key.
+fn menu<S: AsRef<str>, T: Terminal>(
+    header: &str, options: &[S], width: i32, root: &mut T
+) -> Option<usize> {
+    assert!(options.len() <= 26, "Cannot have a menu with more than 26 options.");
+
+    // The header wraps, so work out how tall it will be
+    let header_height = if header.is_empty() {
+        0
+    } else {
+        terminal::wrap_text(header, width).len() as i32
+    };
+    let height = options.len() as i32 + header_height;
+
+    let mut window = root.new_layer(width, height);
+    window.print_rect(0, 0, width, WHITE, header);
+
+    for (index, option_text) in options.iter().enumerate() {
+        let menu_letter = (b'a' + index as u8) as char;
+        let text = format!("({}) {}", menu_letter, option_text.as_ref());
+        window.print(0, header_height + index as i32, Align::Left, WHITE, &text);
+    }
+
+    let x = SCREEN_WIDTH / 2 - width / 2;
+    let y = SCREEN_HEIGHT / 2 - height / 2;
+    root.blit(&window, (x, y), OPAQUE, MENU_BACKGROUND_ALPHA);
+    root.flush();
+
+    if let Some(Key { code: KeyCode::Char(letter), .. }) = root.wait_for_keypress() {
+        if letter.is_ascii_alphabetic() {
+            let index = letter.to_ascii_lowercase() as usize - 'a' as usize;
+            if index < options.len() {
+                return Some(index);
+            }
+        }
+    }
+    None
+}
+
+fn msgbox<T: Terminal>(text: &str, width: i32, root: &mut T) {
+    let options: &[&str] = &[];
+    menu(text, options, width, root);
+}
+
+
+fn inventory_menu<T: Terminal>(inventory: &[Object], header: &str, root: &mut T) -> Option<usize> {
+    let options = if inventory.is_empty() {
+        vec!["Inventory is empty.".into()]
+    } else {
+        inventory
+            .iter()
+            .map(|item| match item.equipment {
+                Some(equipment) if equipment.equipped => {
+                    format!("{} (on {})", item.name, equipment.slot)
+                },
+                _ => item.name.clone(),
+            })
+            .collect()
+    };
+
+    let inventory_index = menu(header, &options, INVENTORY_WIDTH, root);
+
+    // The "empty" line isn't an item
+    if inventory.is_empty() {
+        None
+    } else {
+        inventory_index
+    }
+}
+
+
+// Lets the player move a cursor over the map, with the keyboard or the mouse,
+// to pick a tile in view (and optionally within range of the player). None
+// if cancelled with Escape or a right click.
+fn target_tile<T: Terminal>(
+    ui: &mut Ui<T>, game: &Game, objects: &[Object], max_range: Option<f32>
+) -> Option<(i32, i32)> {
+    let (mut x, mut y) = objects[PLAYER].pos();
+    loop {
+        ui.root.clear();
+        ui.con.clear();
+        render_all(ui, game, objects);
+
+        // Highlight the cursor, say what is under it and explain the
+        // controls over the map
+        if let Some((screen_x, screen_y)) = ui.camera.map_to_screen(x, y) {
+            ui.root.set_bg(screen_x, screen_y, LIGHT_YELLOW);
+        }
+        ui.root.print(
+            1,
+            0,
+            Align::Left,
+            WHITE,
+            "Movement keys or mouse move the cursor, Enter or click targets, Escape cancels",
+        );
+        ui.root.print(1, 1, Align::Left, LIGHT_GREY, &get_names_under(x, y, game, objects));
+        ui.root.flush();
+
+        if ui.root.is_closed() {
+            return None;
+        }
+
+        let mut confirm = false;
+        match ui.root.check_for_event() {
+            Some(Event::Mouse(mouse)) => {
+                ui.mouse = mouse;
+                if mouse.cy < VIEW_HEIGHT {
+                    (x, y) = ui.camera.screen_to_map(mouse.cx, mouse.cy);
+                }
+                if mouse.rbutton_pressed {
+                    return None;
+                }
+                confirm = mouse.lbutton_pressed;
+            },
+            // The cursor follows the movement keys, whatever they are bound to
+            Some(Event::Key(key)) => match (key.code, ui.bindings.action(key)) {
+                (KeyCode::Enter, _) => confirm = true,
+                (KeyCode::Escape, _) | (_, Some(Action::Quit)) => return None,
+                (_, Some(Action::Move(dx, dy))) => {
+                    x += dx;
+                    y += dy;
+                },
+                _ => {}
+            },
+            None => {}
+        }
+
+        if confirm {
+            let in_range = max_range.is_none_or(|range| objects[PLAYER].distance(x, y) <= range);
+            if game.fov.is_in_fov(x, y) && in_range {
+                return Some((x, y));
+            }
+        }
+    }
+}
+
+// As target_tile, but only accepts a tile with a monster on it
+fn target_monster<T: Terminal>(
+    ui: &mut Ui<T>, game: &Game, objects: &[Object], max_range: Option<f32>
+) -> Option<usize> {
+    loop {
+        let (x, y) = target_tile(ui, game, objects, max_range)?;
+        let monster = objects
+            .iter()
+            .position(|object| object.pos() == (x, y) && object.fighter.is_some());
+        match monster {
+            Some(id) if id != PLAYER => return Some(id),
+            _ => {}
+        }
+    }
+}
 
-    // FPS limit on loop, and therefore wait time (when waiting for user input)
-    tcod::system::set_fps(LIMIT_FPS);
 
-    // Object creation
-    let pc = Object::new(25, 23, '@', WHITE);
-    let npc = Object::new(SCREEN_WIDTH / 2 - 5, SCREEN_HEIGHT / 2, '@', DARK_YELLOW);
+// Stops early if a game can't be saved, passing the error on
+fn main_menu<T: Terminal>(ui: &mut Ui<T>, options: &Options) -> Result<(), String> {
+    while !ui.root.is_closed() {
+        ui.root.clear();
+        ui.root.print(
+            SCREEN_WIDTH / 2,
+            SCREEN_HEIGHT / 2 - 6,
+            Align::Center,
+            LIGHT_YELLOW,
+            "RUST-GAME",
+        );
 
-    let mut objects = [pc, npc];
-    let game = Game { map: make_map(), };
+        // Continue is only offered when there is something to continue
+        let can_continue = options.save && Path::new(SAVE_FILE).exists();
+        let mut choices = vec!["New game"];
+        if can_continue {
+            choices.push("Continue");
+        }
+        choices.push("Quit");
+
+        let choice = menu("", &choices, MAIN_MENU_WIDTH, &mut ui.root);
+        match choice.map(|index| choices[index]) {
+            Some("New game") => {
+                let (game, objects) = new_game(options);
+                play_game(ui, game, objects, options)?;
+            },
+            Some("Continue") => match load_game(Path::new(SAVE_FILE)) {
+                Ok((game, objects)) => play_game(ui, game, objects, options)?,
+                Err(e) => {
+                    let text = format!("Can't continue: {}", e);
+                    msgbox(&text, SCREEN_WIDTH / 2, &mut ui.root);
+                }
+            },
+            Some("Quit") => break,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+
+// Experience the player needs to get from this level to the next
+fn level_up_xp(level: i32) -> i32 {
+    LEVEL_UP_BASE + level * LEVEL_UP_FACTOR
+}
+
+// Once the player has enough experience, they go up a level and pick a stat
+// to improve. Repeats in case a single kill was worth several levels.
+fn level_up<T: Terminal>(ui: &mut Ui<T>, game: &mut Game, objects: &mut [Object]) {
+    while objects[PLAYER].fighter.is_some_and(|f| f.xp >= level_up_xp(objects[PLAYER].level)) {
+        let pc = &mut objects[PLAYER];
+        let fighter = pc.fighter.as_mut().unwrap();
+        let choices = [
+            format!("Constitution (+20 HP, from {})", fighter.base_max_hp),
+            format!("Strength (+1 attack, from {})", fighter.base_power),
+            format!("Agility (+1 defense, from {})", fighter.base_defense),
+        ];
+        let header = format!("You reached level {}! Choose a stat to raise:\n", pc.level + 1);
+        let mut choice = None;
+        while choice.is_none() {
+            // Closing the window leaves the level-up for the next session
+            if ui.root.is_closed() {
+                return;
+            }
+            choice = menu(&header, &choices, LEVEL_SCREEN_WIDTH, &mut ui.root);
+        }
 
-    // Game loop
-    while !tcod.root.window_closed() {
+        fighter.xp -= level_up_xp(pc.level);
+        match choice {
+            Some(0) => {
+                fighter.base_max_hp += 20;
+                fighter.hp += 20;
+            },
+            Some(1) => fighter.base_power += 1,
+            _ => fighter.base_defense += 1,
+        }
+        pc.level += 1;
+        game.messages.add(
+            format!("Your battle skills grow stronger! You reached level {}!", pc.level),
+            YELLOW,
+        );
+    }
+}
+
+
+// Runs one game until the player quits (saving on the way out) or dies.
+// Failing to save is returned rather than printed, as the terminal may still
+// be taken over by the frontend.
+fn play_game<T: Terminal>(
+    ui: &mut Ui<T>, mut game: Game, mut objects: Vec<Object>, options: &Options
+) -> Result<(), String> {
+    while !ui.root.is_closed() {
         // Clear for new frame
-        tcod.con.clear();
+        ui.root.clear();
+        ui.con.clear();
 
         // Draw all
-        render_all(&mut tcod, &game, &objects);
-        tcod.root.flush();
+        render_all(ui, &game, &objects);
+        ui.root.flush();
 
-        // Handle input
-        let pc = &mut objects[0];
-        let exit = handle_keys(&mut tcod, pc, &game);
-        if exit {
+        // Handle input. Mouse movement only updates the tooltip; the
+        // keyboard drives the turn.
+        match ui.root.check_for_event() {
+            Some(Event::Mouse(mouse)) => {
+                ui.mouse = mouse;
+                ui.key = None;
+            },
+            Some(Event::Key(key)) => ui.key = Some(key),
+            None => ui.key = None,
+        }
+        let previous_player_position = objects[PLAYER].pos();
+        let player_action = handle_keys(ui, &mut game, &mut objects);
+        if player_action == PlayerAction::Exit {
             break;
         }
+
+        level_up(ui, &mut game, &mut objects);
+
+        // Recompute FOV only when the player has moved, before monsters
+        // decide whether they can see the player
+        if objects[PLAYER].pos() != previous_player_position {
+            update_fov(&mut game, &objects[PLAYER]);
+        }
+
+        // The rest of the world moves after every player action that used a turn
+        if objects[PLAYER].alive && player_action == PlayerAction::TookTurn {
+            take_game_turn(&mut game, &mut objects);
+        }
+
+        if !objects[PLAYER].alive {
+            ui.root.clear();
+            ui.con.clear();
+            render_all(ui, &game, &objects);
+            msgbox("You died! Press any key.", SCREEN_WIDTH / 3, &mut ui.root);
+            break;
+        }
+    }
+
+    // Save on the way out. A dead player has nothing left to continue.
+    if !options.save {
+        return Ok(());
+    }
+    if objects[PLAYER].alive {
+        save_game(Path::new(SAVE_FILE), &game, &objects).map_err(|e| format!("Could not save the game: {}", e))
+    } else {
+        match fs::remove_file(SAVE_FILE) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                Err(format!("Could not remove the old save: {}", e))
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+
+fn main() {
+    let options = parse_args();
+
+    // Load before opening the window, so a bad save file fails fast
+    let saved = if options.continue_game {
+        match load_game(Path::new(SAVE_FILE)) {
+            Ok(saved) => Some(saved),
+            Err(e) => {
+                eprintln!("Can't continue: {}", e);
+                process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
+    // Key bindings come from --keys, or else keybindings.cfg if there is one.
+    // Headless runs ignore the latter so scripts behave the same everywhere.
+    let mut bindings = KeyBindings::new();
+    let keys_file = options.keys_file.clone().or_else(|| {
+        let use_default = options.headless.is_none() && Path::new(KEYBINDINGS_FILE).exists();
+        use_default.then(|| KEYBINDINGS_FILE.to_string())
+    });
+    if let Some(path) = keys_file {
+        let loaded = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| bindings.load(&text));
+        if let Err(e) = loaded {
+            eprintln!("Can't load key bindings from {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+
+    // Headless runs play their script straight into a game (a fresh one
+    // unless continuing) and print the final screen
+    if let Some(path) = &options.headless {
+        let script = fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| headless::parse_script(&text))
+            .unwrap_or_else(|e| {
+                eprintln!("Can't run script {}: {}", path, e);
+                process::exit(1);
+            });
+        let start = saved.unwrap_or_else(|| new_game(&options));
+        let root = Headless::new(SCREEN_WIDTH, SCREEN_HEIGHT, script);
+        let (root, result) = run(root, bindings, Some(start), &options);
+        print!("{}", root.dump());
+        report(result);
+        return;
+    }
+
+    match options.frontend {
+        #[cfg(feature = "tcod")]
+        Frontend::Tcod => {
+            let root =
+                tcod_terminal::open_window(SCREEN_WIDTH, SCREEN_HEIGHT, "Rust-game", LIMIT_FPS);
+            let (_, result) = run(root, bindings, saved, &options);
+            report(result);
+        },
+        Frontend::Ansi => match Ansi::open(SCREEN_WIDTH, SCREEN_HEIGHT, LIMIT_FPS) {
+            Ok(root) => {
+                // Give the terminal back before saying anything on it
+                let (root, result) = run(root, bindings, saved, &options);
+                drop(root);
+                report(result);
+            },
+            Err(e) => {
+                eprintln!("Can't start the terminal frontend: {}", e);
+                process::exit(1);
+            }
+        },
+    }
+}
+
+
+// Exits with an error once the frontend is closed, if the game ended badly
+fn report(result: Result<(), String>) {
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+
+// Plays on the given terminal until the player quits, handing the terminal
+// back afterwards along with any error saving the game
+fn run<T: Terminal>(
+    mut root: T, bindings: KeyBindings, saved: Option<(Game, Vec<Object>)>, options: &Options
+) -> (T, Result<(), String>) {
+    // Game-layer console properties
+    let con = root.new_layer(VIEW_WIDTH, VIEW_HEIGHT);
+    let panel = root.new_layer(SCREEN_WIDTH, PANEL_HEIGHT);
+    let camera = Camera::new(VIEW_WIDTH, VIEW_HEIGHT);
+
+    let mut ui = Ui {
+        root,
+        con,
+        panel,
+        camera,
+        bindings,
+        key: None,
+        mouse: Default::default(),
+    };
+
+    // --continue skips straight into the saved game
+    let mut result = Ok(());
+    if let Some((game, objects)) = saved {
+        result = play_game(&mut ui, game, objects, options);
+    }
+
+    if result.is_ok() {
+        result = main_menu(&mut ui, options);
+    }
+    (ui.root, result)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Plays a script through the headless terminal and compares the final
+    // screen with tests/golden/<name>.txt. Run with UPDATE_GOLDEN=1 to write
+    // the current screen out as the new golden file instead.
+    fn check_golden(name: &str, options: &Options) {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden");
+        let script = fs::read_to_string(dir.join(format!("{}.keys", name))).unwrap();
+        let script = headless::parse_script(&script).unwrap();
+
+        let root = Headless::new(SCREEN_WIDTH, SCREEN_HEIGHT, script);
+        let start = new_game(options);
+        let (root, result) = run(root, KeyBindings::new(), Some(start), options);
+        result.unwrap();
+        let screen = root.dump();
+
+        let golden = dir.join(format!("{}.txt", name));
+        if env::var_os("UPDATE_GOLDEN").is_some() {
+            fs::write(&golden, &screen).unwrap();
+        }
+        let expected = fs::read_to_string(&golden).unwrap();
+        assert!(screen == expected, "screen differs from {}:\n{}", golden.display(), screen);
+    }
+
+    fn test_options(seed: u64, map_style: MapStyle) -> Options {
+        Options {
+            seed: Some(seed),
+            map_style,
+            map_size: (MAP_WIDTH, MAP_HEIGHT),
+            continue_game: false,
+            frontend: DEFAULT_FRONTEND,
+            keys_file: None,
+            headless: None,
+            save: false,
+        }
+    }
+
+    #[test]
+    fn key_bindings_report_actions_left_without_keys() {
+        let mut bindings = KeyBindings::new();
+        assert!(bindings.load("look: g").unwrap_err().contains("'pick_up'"));
+
+        let mut bindings = KeyBindings::new();
+        bindings.load("look: g\npick_up:").unwrap();
+        assert_eq!(bindings.action("g".parse().unwrap()), Some(Action::Look));
+        assert_eq!(bindings.action("l".parse().unwrap()), None);
+
+        for file in ["vi-keys.cfg", "wasd.cfg"] {
+            let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("keybindings").join(file);
+            KeyBindings::new().load(&fs::read_to_string(path).unwrap()).unwrap();
+        }
+    }
+
+    #[test]
+    fn rest_ends_at_full_health_or_with_a_monster_in_view() {
+        for seed in 0..20 {
+            let (mut game, mut objects) = new_game(&test_options(seed, MapStyle::Rooms));
+            objects[PLAYER].fighter.as_mut().unwrap().hp = 5;
+            rest(&mut game, &mut objects);
+
+            let full = objects[PLAYER].fighter.unwrap().hp == objects[PLAYER].max_hp(&game);
+            let seen = monster_in_view(&game, &objects).is_some();
+            assert!(full || seen || !objects[PLAYER].alive, "seed {}", seed);
+        }
+    }
+
+    // A scratch file name in the system's temp directory, unique to this run
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        env::temp_dir().join(format!("rust-game-{}-{}", process::id(), name))
+    }
+
+    #[test]
+    fn saved_games_load_back_unchanged() {
+        let path = temp_path("round-trip");
+        let (game, objects) = new_game(&test_options(1, MapStyle::Caves));
+        save_game(&path, &game, &objects).unwrap();
+        let (loaded_game, loaded_objects) = load_game(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(!path.with_extension("tmp").exists());
+
+        let as_json = |game: &Game, objects: &[Object]| {
+            serde_json::to_string(&SaveFile { version: SAVE_VERSION, game, objects }).unwrap()
+        };
+        assert_eq!(as_json(&loaded_game, &loaded_objects), as_json(&game, &objects));
+    }
+
+    #[test]
+    fn bad_saves_are_reported() {
+        let path = temp_path("bad-save");
+        assert!(matches!(load_game(&path), Err(LoadError::Io(_))));
+
+        fs::write(&path, "{\"version\": 1, \"game\": ").unwrap();
+        assert!(matches!(load_game(&path), Err(LoadError::Corrupt(_))));
+
+        fs::write(&path, "{\"version\": 1}").unwrap();
+        assert!(matches!(load_game(&path), Err(LoadError::IncompatibleVersion(1))));
+
+        let current = format!("{{\"version\": {}, \"game\": {{}}}}", SAVE_VERSION);
+        fs::write(&path, current).unwrap();
+        assert!(matches!(load_game(&path), Err(LoadError::Corrupt(_))));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn dropping_a_max_hp_bonus_clamps_hp() {
+        let (mut game, mut objects) = new_game(&test_options(1, MapStyle::Rooms));
+        let ring = make_equipment(0, 0, '=', "ring of vitality", GOLD, Equipment {
+            slot: Slot::Ring,
+            equipped: true,
+            power_bonus: 0,
+            defense_bonus: 0,
+            max_hp_bonus: 10,
+        });
+        game.inventory.push(ring);
+        objects[PLAYER].fighter.as_mut().unwrap().hp = 40;
+
+        drop_item(game.inventory.len() - 1, &mut game, &mut objects);
+        assert_eq!(objects[PLAYER].fighter.unwrap().hp, 30);
+    }
+
+    #[test]
+    fn golden_rooms_seed1() {
+        check_golden("rooms-seed1", &test_options(1, MapStyle::Rooms));
+    }
+
+    #[test]
+    fn every_map_style_is_fully_connected() {
+        let styles = [MapStyle::Rooms, MapStyle::Bsp, MapStyle::Caves];
+        let sizes = [(MIN_MAP_SIZE, MIN_MAP_SIZE), (MAP_WIDTH, MAP_HEIGHT), (120, 90)];
+        for style in styles {
+            for (width, height) in sizes {
+                for seed in 0..40 {
+                    let mut objects = vec![Object::new(0, 0, '@', "player", WHITE, true)];
+                    let mut rng = level_rng(seed, 1);
+                    let map = make_map(&mut objects, style, width, height, 1, &mut rng);
+
+                    let regions = find_regions(&map);
+                    let context = format!("{:?} {}x{} seed {}", style, width, height, seed);
+                    assert_eq!(regions.len(), 1, "{}", context);
+                    assert!(regions[0].contains(&objects[PLAYER].pos()), "{}", context);
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,150 @@
+// The libtcod window: Root is the screen and Offscreen consoles its layers
+
+use tcod::colors::{self as tcod_colors, Color as TcodColor};
+use tcod::console::*;
+use tcod::input::{self, KeyCode as TcodKeyCode};
+
+use crate::colors::Color;
+use crate::terminal::{Canvas, Event, Key, KeyCode, Mouse, Terminal};
+
+
+pub fn open_window(width: i32, height: i32, title: &str, limit_fps: i32) -> Root {
+    let root = Root::initializer()
+        .font("arial10x10.png", FontLayout::Tcod)
+        .font_type(FontType::Greyscale)
+        .size(width, height)
+        .title(title)
+        .init();
+
+    // FPS limit on loop, and therefore wait time (when waiting for user input)
+    tcod::system::set_fps(limit_fps);
+    root
+}
+
+
+// Letters and symbols arrive as Text; the matching Char events are dropped so
+// each key press counts once
+fn convert_key(key: input::Key) -> Option<Key> {
+    let code = match key.code {
+        TcodKeyCode::Text => KeyCode::Char(key.text().chars().next()?),
+        TcodKeyCode::Up => KeyCode::Up,
+        TcodKeyCode::Down => KeyCode::Down,
+        TcodKeyCode::Left => KeyCode::Left,
+        TcodKeyCode::Right => KeyCode::Right,
+        TcodKeyCode::Home => KeyCode::Home,
+        TcodKeyCode::End => KeyCode::End,
+        TcodKeyCode::PageUp => KeyCode::PageUp,
+        TcodKeyCode::PageDown => KeyCode::PageDown,
+        TcodKeyCode::Enter | TcodKeyCode::NumPadEnter => KeyCode::Enter,
+        TcodKeyCode::Escape => KeyCode::Escape,
+        TcodKeyCode::Backspace => KeyCode::Backspace,
+        TcodKeyCode::Tab => KeyCode::Tab,
+        TcodKeyCode::NumPad0 => KeyCode::NumPad(0),
+        TcodKeyCode::NumPad1 => KeyCode::NumPad(1),
+        TcodKeyCode::NumPad2 => KeyCode::NumPad(2),
+        TcodKeyCode::NumPad3 => KeyCode::NumPad(3),
+        TcodKeyCode::NumPad4 => KeyCode::NumPad(4),
+        TcodKeyCode::NumPad5 => KeyCode::NumPad(5),
+        TcodKeyCode::NumPad6 => KeyCode::NumPad(6),
+        TcodKeyCode::NumPad7 => KeyCode::NumPad(7),
+        TcodKeyCode::NumPad8 => KeyCode::NumPad(8),
+        TcodKeyCode::NumPad9 => KeyCode::NumPad(9),
+        _ => return None,
+    };
+    Some(Key { code, alt: key.alt, ctrl: key.ctrl })
+}
+
+
+fn convert_mouse(mouse: input::Mouse) -> Mouse {
+    Mouse {
+        cx: mouse.cx as i32,
+        cy: mouse.cy as i32,
+        lbutton_pressed: mouse.lbutton_pressed,
+        rbutton_pressed: mouse.rbutton_pressed,
+    }
+}
+
+
+fn convert_color(color: Color) -> TcodColor {
+    TcodColor::new(color.r, color.g, color.b)
+}
+
+
+fn in_bounds<C: Console>(console: &C, x: i32, y: i32) -> bool {
+    x >= 0 && y >= 0 && x < console.width() && y < console.height()
+}
+
+
+impl<C: Console> Canvas for C {
+    fn size(&self) -> (i32, i32) {
+        (self.width(), self.height())
+    }
+
+    fn clear(&mut self) {
+        self.set_default_background(tcod_colors::BLACK);
+        Console::clear(self);
+    }
+
+    fn put_glyph(&mut self, x: i32, y: i32, glyph: char, fg: Color) {
+        if in_bounds(self, x, y) {
+            self.put_char(x, y, glyph, BackgroundFlag::None);
+            self.set_char_foreground(x, y, convert_color(fg));
+        }
+    }
+
+    fn set_bg(&mut self, x: i32, y: i32, bg: Color) {
+        if in_bounds(self, x, y) {
+            self.set_char_background(x, y, convert_color(bg), BackgroundFlag::Set);
+        }
+    }
+}
+
+
+impl Terminal for Root {
+    type Layer = Offscreen;
+
+    fn new_layer(&mut self, width: i32, height: i32) -> Offscreen {
+        Offscreen::new(width, height)
+    }
+
+    fn blit(&mut self, layer: &Offscreen, to: (i32, i32), fg_alpha: f32, bg_alpha: f32) {
+        blit(layer, (0, 0), layer.size(), self, to, fg_alpha, bg_alpha);
+    }
+
+    fn flush(&mut self) {
+        Root::flush(self);
+    }
+
+    fn is_closed(&self) -> bool {
+        self.window_closed()
+    }
+
+    fn toggle_fullscreen(&mut self) {
+        let is_fullscreen = self.is_fullscreen();
+        self.set_fullscreen(!is_fullscreen);
+    }
+
+    fn wait_for_keypress(&mut self) -> Option<Key> {
+        // Only flush stale input before the first wait: a letter arrives as a
+        // Char event followed by the Text one, and flushing after skipping
+        // the Char would throw the Text away with it
+        let mut flush = true;
+        loop {
+            let key = Root::wait_for_keypress(self, flush);
+            if self.window_closed() {
+                return None;
+            }
+            if let Some(key) = convert_key(key) {
+                return Some(key);
+            }
+            flush = false;
+        }
+    }
+
+    fn check_for_event(&mut self) -> Option<Event> {
+        match input::check_for_event(input::MOUSE | input::KEY_PRESS)? {
+            (_, input::Event::Key(key)) => convert_key(key).map(Event::Key),
+            (_, input::Event::Mouse(mouse)) => Some(Event::Mouse(convert_mouse(mouse))),
+        }
+    }
+}
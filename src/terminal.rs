@@ -0,0 +1,202 @@
+// The interface the game draws through, so that it doesn't care whether it
+// ends up in a libtcod window or somewhere else
+
+use std::mem;
+use std::str::FromStr;
+
+use crate::colors::Color;
+
+
+// Keys the game understands, whatever the terminal calls them
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum KeyCode {
+    // Anything printable, as typed (so already shifted)
+    Char(char),
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Enter,
+    Escape,
+    Backspace,
+    Tab,
+    // Numpad digits, kept apart from the number row
+    NumPad(u8),
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Key {
+    pub code: KeyCode,
+    pub alt: bool,
+    pub ctrl: bool,
+}
+
+
+// Keys by name, as used in scripts and key binding files: single characters
+// stand for themselves, everything else is named (up, enter, numpad7, ...)
+// and may be prefixed with alt+ or ctrl+
+impl FromStr for Key {
+    type Err = String;
+
+    fn from_str(token: &str) -> Result<Key, String> {
+        let mut rest = token;
+        let mut alt = false;
+        let mut ctrl = false;
+        loop {
+            if let Some(stripped) = rest.strip_prefix("alt+") {
+                alt = true;
+                rest = stripped;
+            } else if let Some(stripped) = rest.strip_prefix("ctrl+") {
+                ctrl = true;
+                rest = stripped;
+            } else {
+                break;
+            }
+        }
+
+        let mut chars = rest.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match rest {
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "pageup" => KeyCode::PageUp,
+                "pagedown" => KeyCode::PageDown,
+                "enter" => KeyCode::Enter,
+                "escape" => KeyCode::Escape,
+                "backspace" => KeyCode::Backspace,
+                "tab" => KeyCode::Tab,
+                "space" => KeyCode::Char(' '),
+                _ => match rest.strip_prefix("numpad").and_then(|digit| digit.parse().ok()) {
+                    Some(digit) if digit <= 9 => KeyCode::NumPad(digit),
+                    _ => return Err(format!("unknown key '{}'", token)),
+                },
+            },
+        };
+        Ok(Key { code, alt, ctrl })
+    }
+}
+
+
+// Where the mouse is, in screen cells, and which buttons were just clicked
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Mouse {
+    pub cx: i32,
+    pub cy: i32,
+    pub lbutton_pressed: bool,
+    pub rbutton_pressed: bool,
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    Key(Key),
+    Mouse(Mouse),
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Align {
+    Left,
+    Center,
+}
+
+
+// A grid of character cells, each with a glyph and a foreground and
+// background colour. Writes outside the grid are ignored.
+pub trait Canvas {
+    fn size(&self) -> (i32, i32);
+
+    // Blank every cell on a black background
+    fn clear(&mut self);
+
+    fn put_glyph(&mut self, x: i32, y: i32, glyph: char, fg: Color);
+
+    fn set_bg(&mut self, x: i32, y: i32, bg: Color);
+
+    // A single line of text, left-aligned on x or centred on it
+    fn print(&mut self, x: i32, y: i32, align: Align, fg: Color, text: &str) {
+        let width = text.chars().count() as i32;
+        let start_x = match align {
+            Align::Left => x,
+            Align::Center => x - width / 2,
+        };
+        for (i, glyph) in text.chars().enumerate() {
+            self.put_glyph(start_x + i as i32, y, glyph, fg);
+        }
+    }
+
+    // Text word-wrapped to the given width, returning how many lines it took
+    fn print_rect(&mut self, x: i32, y: i32, width: i32, fg: Color, text: &str) -> i32 {
+        let lines = wrap_text(text, width);
+        for (i, line) in lines.iter().enumerate() {
+            self.print(x, y + i as i32, Align::Left, fg, line);
+        }
+        lines.len() as i32
+    }
+}
+
+
+// The screen itself: layers are drawn separately and blitted onto it
+pub trait Terminal: Canvas {
+    type Layer: Canvas;
+
+    fn new_layer(&mut self, width: i32, height: i32) -> Self::Layer;
+
+    // Copy a whole layer onto the screen with its top-left corner at `to`,
+    // blending its colours over what is already there by the given alphas
+    fn blit(&mut self, layer: &Self::Layer, to: (i32, i32), fg_alpha: f32, bg_alpha: f32);
+
+    // Show everything drawn since the last flush
+    fn flush(&mut self);
+
+    fn is_closed(&self) -> bool;
+
+    fn toggle_fullscreen(&mut self);
+
+    // Blocks until a key is pressed; None if the terminal closed meanwhile
+    fn wait_for_keypress(&mut self) -> Option<Key>;
+
+    // The next pending key press or mouse event, if any
+    fn check_for_event(&mut self) -> Option<Event>;
+}
+
+
+// Splits text into lines no wider than `width`, breaking at spaces where
+// possible and always at newlines
+pub fn wrap_text(text: &str, width: i32) -> Vec<String> {
+    let width = width.max(1) as usize;
+    let mut lines = vec![];
+    for paragraph in text.split('\n') {
+        let mut line = String::new();
+        for word in paragraph.split(' ') {
+            let line_len = line.chars().count();
+            let word_len = word.chars().count();
+            if line_len > 0 && line_len + 1 + word_len > width {
+                lines.push(mem::take(&mut line));
+            } else if line_len > 0 {
+                line.push(' ');
+            }
+
+            // Words too long for a line of their own get cut up
+            let mut rest: Vec<char> = word.chars().collect();
+            while rest.len() > width {
+                let tail = rest.split_off(width);
+                lines.push(rest.into_iter().collect());
+                rest = tail;
+            }
+            line.extend(rest);
+        }
+        lines.push(line);
+    }
+    lines
+}